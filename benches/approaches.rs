@@ -0,0 +1,139 @@
+//! Times aggregators on generated input held in memory.
+//!
+//!     cargo bench --bench approaches -- [approach_N ...]
+//!
+//! `BENCH_ROWS` sets the input size (default 10M rows, `K`/`M`/`G` suffixes accepted)
+//! and `BENCH_10K=1` switches to 10,000 distinct stations. Without arguments the best
+//! approaches are compared.
+//!
+//! `BENCH_FILE=<path>` times opening and aggregating that file instead, once per input
+//! backend that the approach supports, with the page cache warm and, on Unix, with the
+//! file evicted from it before every run. Eviction only drops clean pages, so sync a
+//! freshly generated file first.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use rs_1brc::aggregator::{by_name, Aggregator};
+use rs_1brc::generate::{self, Config, StationSet};
+use rs_1brc::input::Backend;
+
+const RUNS: usize = 5;
+const DEFAULT: &[&str] = &["approach_9", "approach_10", "approach_11"];
+
+fn rows() -> u64 {
+    let value = std::env::var("BENCH_ROWS").unwrap_or_else(|_| "10M".to_string());
+    let (digits, scale) = match value.as_bytes().last() {
+        Some(b'K' | b'k') => (&value[..value.len() - 1], 1_000),
+        Some(b'M' | b'm') => (&value[..value.len() - 1], 1_000_000),
+        Some(b'G' | b'g') => (&value[..value.len() - 1], 1_000_000_000),
+        _ => (&value[..], 1),
+    };
+    digits.parse::<u64>().expect("BENCH_ROWS is a row count") * scale
+}
+
+fn aggregator(name: &str) -> &'static dyn Aggregator {
+    by_name(name).unwrap_or_else(|| {
+        eprintln!("unknown approach `{}`", name);
+        std::process::exit(2);
+    })
+}
+
+/// Times `f` `RUNS` times and prints the median, the minimum and the throughput.
+fn report<F: FnMut() -> Duration>(label: &str, len: usize, mut f: F) {
+    let mut times: Vec<Duration> = (0..RUNS).map(|_| f()).collect();
+    times.sort();
+    let median = times[RUNS / 2];
+    let throughput = len as f64 / median.as_secs_f64() / (1 << 30) as f64;
+    println!(
+        "{:<22} median {:>10.3?}  min {:>10.3?}  {:>6.2} GiB/s",
+        label, median, times[0], throughput
+    );
+}
+
+/// Drops the pages of `path` from the page cache.
+#[cfg(unix)]
+fn evict(path: &Path) -> bool {
+    use std::os::unix::io::AsRawFd;
+
+    let file = std::fs::File::open(path).unwrap();
+    unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) == 0 }
+}
+
+#[cfg(not(unix))]
+fn evict(_: &Path) -> bool {
+    false
+}
+
+fn files(path: &Path, names: &[&str]) {
+    let len = std::fs::metadata(path).unwrap().len() as usize;
+    let cold = evict(path);
+    println!(
+        "{}, {} MiB, {} threads",
+        path.display(),
+        len >> 20,
+        rayon::current_num_threads()
+    );
+    for &name in names {
+        let aggregator = aggregator(name);
+        for backend in Backend::ALL {
+            if backend == Backend::Read && !aggregator.preads() {
+                continue;
+            }
+            for evicted in [false, true] {
+                if evicted && !cold {
+                    continue;
+                }
+                let cache = if evicted { "cold" } else { "warm" };
+                report(&format!("{} {} {}", name, backend, cache), len, || {
+                    if evicted {
+                        evict(path);
+                    }
+                    let start = Instant::now();
+                    std::hint::black_box(aggregator.aggregate_file_with(path, backend).unwrap());
+                    start.elapsed()
+                });
+            }
+        }
+    }
+}
+
+fn main() {
+    // `cargo bench` passes `--bench` along; everything else names an approach.
+    let names: Vec<String> = std::env::args().skip(1).filter(|arg| !arg.starts_with('-')).collect();
+    let names: Vec<&str> = if names.is_empty() {
+        DEFAULT.to_vec()
+    } else {
+        names.iter().map(String::as_str).collect()
+    };
+    if let Ok(path) = std::env::var("BENCH_FILE") {
+        return files(Path::new(&path), &names);
+    }
+
+    let config = Config {
+        rows: rows(),
+        seed: 0,
+        stations: match std::env::var("BENCH_10K").as_deref() {
+            Ok("1") => StationSet::Distinct10k,
+            _ => StationSet::Official,
+        },
+    };
+    let mut input = Vec::new();
+    generate::generate(&mut input, &config).unwrap();
+    println!(
+        "{} rows, {:?} stations, {} MiB, {} threads",
+        config.rows,
+        config.stations,
+        input.len() >> 20,
+        rayon::current_num_threads()
+    );
+
+    for name in names {
+        let aggregator = aggregator(name);
+        report(name, input.len(), || {
+            let start = Instant::now();
+            std::hint::black_box(aggregator.aggregate(&input).unwrap());
+            start.elapsed()
+        });
+    }
+}
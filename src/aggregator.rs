@@ -0,0 +1,91 @@
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::error::{Aggregate, Error, ErrorSink, OnError, ParseError};
+use crate::input::{Backend, Input};
+use crate::stats::Stations;
+use crate::stream::{aggregate_reader, BUFFER_SIZE};
+use crate::{
+    approach_0, approach_1, approach_10, approach_11, approach_2, approach_3, approach_4,
+    approach_5, approach_6, approach_7, approach_8, approach_9,
+};
+
+pub trait Aggregator: Sync {
+    /// Aggregates `input`, handing every malformed line to `errors`.
+    ///
+    /// Returns early with the sink's error when its policy is [`OnError::Fail`].
+    fn try_aggregate(&self, input: &[u8], errors: &mut ErrorSink) -> Result<Stations, ParseError>;
+
+    fn aggregate_with(&self, input: &[u8], on_error: OnError) -> Result<Aggregate, ParseError> {
+        let mut errors = ErrorSink::new(on_error, input);
+        match self.try_aggregate(input, &mut errors) {
+            Ok(stations) => Ok(errors.finish(input, stations)),
+            Err(err) => Err(err.locate(input)),
+        }
+    }
+
+    /// Aggregates `input`, failing on the first malformed line.
+    fn aggregate(&self, input: &[u8]) -> Result<Stations, ParseError> {
+        self.aggregate_with(input, OnError::Fail)
+            .map(|aggregate| aggregate.stations)
+    }
+
+    fn aggregate_file(&self, path: &Path) -> Result<Stations, Error> {
+        self.aggregate_file_with(path, Backend::default())
+    }
+
+    fn aggregate_file_with(&self, path: &Path, backend: Backend) -> Result<Stations, Error> {
+        let input = backend.open(path)?;
+        Ok(self.aggregate_input(&input, OnError::Fail)?.stations)
+    }
+
+    /// Aggregates an opened input. Files that are read segment by segment, which
+    /// [`Backend::Read`] opens, are an [`io::ErrorKind::Unsupported`] error unless the
+    /// approach [`preads`](Self::preads) them.
+    fn aggregate_input(&self, input: &Input, on_error: OnError) -> Result<Aggregate, Error> {
+        match input {
+            Input::File(_) => Err(Error::Io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "this approach does not schedule segments to read a file with; map it instead",
+            ))),
+            _ => Ok(self.aggregate_with(input.bytes().unwrap_or_default(), on_error)?),
+        }
+    }
+
+    /// Whether every worker reads the segments it claims from an [`Input::File`] into a
+    /// buffer of its own in [`aggregate_input`](Self::aggregate_input).
+    fn preads(&self) -> bool {
+        false
+    }
+
+    /// Aggregates a stream such as stdin or a pipe, in `BUFFER_SIZE` pieces.
+    fn aggregate_reader(
+        &self,
+        reader: &mut (dyn Read + Send),
+        on_error: OnError,
+    ) -> Result<Aggregate, Error> {
+        aggregate_reader(self, reader, on_error, BUFFER_SIZE)
+    }
+}
+
+pub static APPROACHES: [(&str, &dyn Aggregator); 12] = [
+    ("approach_0", &approach_0::Approach),
+    ("approach_1", &approach_1::Approach),
+    ("approach_2", &approach_2::Approach),
+    ("approach_3", &approach_3::Approach),
+    ("approach_4", &approach_4::Approach),
+    ("approach_5", &approach_5::Approach),
+    ("approach_6", &approach_6::Approach),
+    ("approach_7", &approach_7::Approach),
+    ("approach_8", &approach_8::Approach),
+    ("approach_9", &approach_9::Approach),
+    ("approach_10", &approach_10::Approach),
+    ("approach_11", &approach_11::Approach::DEFAULT),
+];
+
+pub fn by_name(name: &str) -> Option<&'static dyn Aggregator> {
+    APPROACHES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, aggregator)| *aggregator)
+}
@@ -1,50 +1,35 @@
 use std::collections::HashMap;
 use std::io::{BufRead, BufReader};
-use std::path::Path;
 
-struct Data {
-    min: f64,
-    max: f64,
-    sum: f64,
-    count: u64,
-}
+use crate::aggregator::Aggregator;
+use crate::error::{ErrorSink, ParseError};
+use crate::parse::{parse_record, station_name};
+use crate::stats::{StationStats, Stations};
 
-pub fn run(path: &Path) {
-    let file = std::fs::File::open(path).unwrap();
-    let reader = BufReader::new(file);
-    let mut result: HashMap<String, Data> = HashMap::new();
-    for line in reader.lines() {
-        let line = line.unwrap();
-        let mut fields = line.split(';');
-        let key = fields.next().unwrap();
-        let key = key.to_string();
-        match fields.next() {
-            None => {
-                println!("Invalid line: {} \n", line);
-                continue;
-            }
-            Some(data) => {
-                let value = data.parse::<f64>().unwrap();
-                result.entry(key)
-                    .and_modify(|e| {
-                        e.min = e.min.min(value);
-                        e.max = e.max.max(value);
-                        e.sum += value;
-                        e.count += 1;
-                    })
-                    .or_insert_with(|| Data {
-                        min: value,
-                        max: value,
-                        sum: value,
-                        count: 1,
-                    });
+pub struct Approach;
+
+impl Aggregator for Approach {
+    fn try_aggregate(&self, input: &[u8], errors: &mut ErrorSink) -> Result<Stations, ParseError> {
+        let reader = BufReader::new(input);
+        let mut result: HashMap<String, StationStats> = HashMap::new();
+        let mut offset = 0;
+        for line in reader.split(b'\n') {
+            let line = line.expect("reading from memory cannot fail");
+            let start = offset;
+            offset += line.len() + 1;
+            let mut fields = line.splitn(2, |&b| b == b';');
+            let key = fields.next().unwrap_or_default();
+            let record = parse_record(key, fields.next())
+                .and_then(|value| Ok((station_name(key)?, value)));
+            match record {
+                Ok((key, value)) => {
+                    result.entry(key.to_string())
+                        .and_modify(|e| e.update(value))
+                        .or_insert_with(|| StationStats::new(value));
+                }
+                Err(kind) => errors.reject_at(start, kind)?,
             }
         }
-    }
-    let keys = result.keys().map(|x| x.to_owned()).collect::<Vec<_>>();
-    for key in keys {
-        let data = result.get(&key).unwrap();
-        // println!("{}: min={}, max={}, avg={}", key, data.min, data.max, data.sum / data.count as f64);
+        Ok(result.into_iter().collect())
     }
 }
-
@@ -0,0 +1,91 @@
+use rayon::prelude::*;
+
+use crate::aggregator::Aggregator;
+use crate::delim::mask64x2;
+use crate::error::{gather, ErrorSink, ParseError};
+use crate::parse::parse_record;
+use crate::partition::partition;
+use crate::stats::{StationStats, Stations};
+use crate::table::{hash_key, StationTable};
+
+const TABLE_SIZE: usize = 1 << 17;
+
+/// Structural-index scanner: every 64-byte block is loaded once to build a `;` mask and
+/// a `\n` mask, and records are cut by walking the set bits, so one load yields several
+/// lines and no byte is searched twice.
+pub struct Approach;
+
+impl Aggregator for Approach {
+    fn try_aggregate(&self, input: &[u8], errors: &mut ErrorSink) -> Result<Stations, ParseError> {
+        let parallel_count = rayon::current_num_threads();
+        let parent = &*errors;
+        let workers = partition(input, parallel_count)
+            .into_par_iter()
+            .map(|range| {
+                let mut errors = parent.fork();
+                let mut result = storage();
+                scan(&input[range], &mut result, &mut errors)?;
+                Ok((result, errors))
+            })
+            .collect();
+        let tables = gather(workers, errors)?;
+        Ok(StationTable::merge_all(tables).to_stations())
+    }
+}
+
+#[inline]
+fn storage() -> StationTable<StationStats> {
+    StationTable::with_capacity(TABLE_SIZE)
+}
+
+fn scan(
+    chunk: &[u8],
+    result: &mut StationTable<StationStats>,
+    errors: &mut ErrorSink,
+) -> Result<(), ParseError> {
+    let mut line_start = 0;
+    // Offset of the first `;` of the current line, or `usize::MAX` before it is seen.
+    let mut semicolon = usize::MAX;
+    let mut base = 0;
+    while base < chunk.len() {
+        let (semicolons, linefeeds) = mask64x2(&chunk[base..], b';', b'\n');
+        let mut structural = semicolons | linefeeds;
+        while structural != 0 {
+            let bit = structural & structural.wrapping_neg();
+            let position = base + bit.trailing_zeros() as usize;
+            if linefeeds & bit != 0 {
+                do_line(chunk, line_start, semicolon, position, result, errors)?;
+                line_start = position + 1;
+                semicolon = usize::MAX;
+            } else if semicolon == usize::MAX {
+                semicolon = position;
+            }
+            structural ^= bit;
+        }
+        base += 64;
+    }
+    if line_start < chunk.len() {
+        do_line(chunk, line_start, semicolon, chunk.len(), result, errors)?;
+    }
+    Ok(())
+}
+
+#[inline(always)]
+fn do_line(
+    chunk: &[u8],
+    start: usize,
+    semicolon: usize,
+    end: usize,
+    result: &mut StationTable<StationStats>,
+    errors: &mut ErrorSink,
+) -> Result<(), ParseError> {
+    let (key, value) = if semicolon == usize::MAX {
+        (&chunk[start..end], None)
+    } else {
+        (&chunk[start..semicolon], Some(&chunk[semicolon + 1..end]))
+    };
+    if let Some(value) = errors.check(key, parse_record(key, value))? {
+        errors.check(key, result.update(key, hash_key(key), value))?;
+    }
+    Ok(())
+}
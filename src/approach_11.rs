@@ -0,0 +1,230 @@
+use crate::aggregator::Aggregator;
+use crate::delim::{find, find32};
+use crate::error::{Aggregate, Error, ErrorSink, OnError, ParseError};
+use crate::input::Input;
+use crate::parse::parse_record;
+use crate::partition::partition;
+use crate::schedule;
+use crate::stats::{StationStats, Stations};
+use crate::table::{hash_key, StationTable};
+
+pub use crate::schedule::SEGMENT_SIZE;
+
+const TABLE_SIZE: usize = 1 << 17;
+pub const MAX_LANES: usize = 8;
+
+/// approach_8's interleaved lanes on approach_9's threads.
+///
+/// Every worker claims the next `segment_size` bytes from a [`schedule::Scheduler`], so
+/// fast workers keep pulling segments while a slow one is stuck on a dense region. Each
+/// segment is split into `lanes` streams that are scanned round-robin: the next line of
+/// every lane is cut before any is hashed, so their loads overlap.
+#[derive(Debug, Clone, Copy)]
+pub struct Approach {
+    lanes: usize,
+    segment_size: usize,
+}
+
+impl Approach {
+    pub const DEFAULT: Approach = Approach::new(3, SEGMENT_SIZE);
+
+    /// Panics unless `1 <= lanes <= MAX_LANES` and `segment_size > 0`.
+    pub const fn new(lanes: usize, segment_size: usize) -> Self {
+        assert!(lanes >= 1 && lanes <= MAX_LANES, "lanes must be within 1..=MAX_LANES");
+        assert!(segment_size > 0, "segment_size must be positive");
+        Approach { lanes, segment_size }
+    }
+
+    pub fn lanes(&self) -> usize {
+        self.lanes
+    }
+}
+
+impl Default for Approach {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl Aggregator for Approach {
+    fn try_aggregate(&self, input: &[u8], errors: &mut ErrorSink) -> Result<Stations, ParseError> {
+        let scan = |segment: &[u8], result: &mut _, errors: &mut _| {
+            self.segment(segment, result, errors)
+        };
+        let merge = StationTable::merge_all;
+        let result = schedule::run(input, self.segment_size, errors, storage, scan, merge)?;
+        Ok(result.to_stations())
+    }
+
+    /// Reads files segment by segment on the workers that scan them.
+    fn aggregate_input(&self, input: &Input, on_error: OnError) -> Result<Aggregate, Error> {
+        let scan = |segment: &[u8], result: &mut _, errors: &mut _| {
+            self.segment(segment, result, errors)
+        };
+        let merge = StationTable::merge_all;
+        let result = schedule::run_input(input, self.segment_size, on_error, storage, scan, merge)?;
+        Ok(result.map(|table| table.to_stations()))
+    }
+
+    fn preads(&self) -> bool {
+        true
+    }
+}
+
+impl Approach {
+    fn segment(
+        &self,
+        segment: &[u8],
+        result: &mut StationTable<StationStats>,
+        errors: &mut ErrorSink,
+    ) -> Result<(), ParseError> {
+        let mut lanes: [&[u8]; MAX_LANES] = [&[]; MAX_LANES];
+        let mut count = 0;
+        for range in partition(segment, self.lanes) {
+            lanes[count] = &segment[range];
+            count += 1;
+        }
+        if count == 0 {
+            // A claimed range that holds no line start.
+            return Ok(());
+        }
+        let mut offsets = [0; MAX_LANES];
+        let mut lines = [None; MAX_LANES];
+
+        'interleaved: loop {
+            // Cut the next line of every lane before hashing any, so their loads overlap.
+            for lane in 0..count {
+                lines[lane] = next_line(lanes[lane], offsets[lane]);
+            }
+            for lane in 0..count {
+                let Some((line, next_offset)) = lines[lane] else {
+                    break 'interleaved;
+                };
+                if let Err(err) = do_line(line, result, errors) {
+                    // Report the earliest bad line, as a sequential scan would.
+                    let earlier =
+                        (0..lane).try_for_each(|i| drain(lanes[i], offsets[i], result, errors));
+                    return Err(earlier.err().unwrap_or(err));
+                }
+                offsets[lane] = next_offset;
+            }
+        }
+
+        for lane in 0..count {
+            drain(lanes[lane], offsets[lane], result, errors)?;
+        }
+        Ok(())
+    }
+}
+
+#[inline]
+fn storage() -> StationTable<StationStats> {
+    StationTable::with_capacity(TABLE_SIZE)
+}
+
+/// Processes the rest of one lane on its own.
+fn drain(
+    lane: &[u8],
+    mut offset: usize,
+    result: &mut StationTable<StationStats>,
+    errors: &mut ErrorSink,
+) -> Result<(), ParseError> {
+    while let Some((line, next_offset)) = next_line(lane, offset) {
+        do_line(line, result, errors)?;
+        offset = next_offset;
+    }
+    Ok(())
+}
+
+type Line<'a> = (&'a [u8], Option<&'a [u8]>);
+
+#[inline]
+fn next_line(segment: &[u8], start: usize) -> Option<(Line<'_>, usize)> {
+    if start >= segment.len() {
+        return None;
+    }
+
+    let semicolon = find_pattern(segment, start, 0x3B);
+    let linefeed = find_pattern(segment, start, 0x0A);
+    if semicolon >= linefeed {
+        return Some(((&segment[start..linefeed], None), linefeed + 1));
+    }
+    let line = (&segment[start..semicolon], Some(&segment[semicolon + 1..linefeed]));
+    Some((line, linefeed + 1))
+}
+
+#[inline]
+fn do_line(
+    (key, val): Line<'_>,
+    result: &mut StationTable<StationStats>,
+    errors: &mut ErrorSink,
+) -> Result<(), ParseError> {
+    if let Some(value) = errors.check(key, parse_record(key, val))? {
+        errors.check(key, result.update(key, hash_key(key), value))?;
+    }
+    Ok(())
+}
+
+#[inline]
+fn find_pattern(segment: &[u8], start: usize, pattern: u8) -> usize {
+    let rest = &segment[start..];
+    if let Some(pos) = find32(rest, pattern) {
+        return start + pos;
+    }
+    rest.get(32..)
+        .and_then(|rest| find(rest, pattern))
+        .map_or(segment.len(), |pos| start + 32 + pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::OnError;
+
+    fn input() -> Vec<u8> {
+        let mut input = Vec::new();
+        for i in 0..500 {
+            let name = "Station".repeat(1 + i % 15);
+            let line = match i % 97 {
+                13 => format!("{}\n", name),
+                41 => format!("{};1.234\n", name),
+                _ => format!("{}{};{}.{}\n", name, i % 11, i % 60, i % 10),
+            };
+            input.extend_from_slice(line.as_bytes());
+        }
+        input.pop();
+        input
+    }
+
+    #[test]
+    fn lanes_and_segments_do_not_change_the_result() {
+        let input = input();
+        let expected = crate::approach_0::Approach.aggregate_with(&input, OnError::Report);
+        let first_error = expected.as_ref().unwrap().errors[0];
+        for threads in [1, 4] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            for lanes in 1..=MAX_LANES {
+                for segment_size in [1, 7, 64, 1000, SEGMENT_SIZE] {
+                    let approach = Approach::new(lanes, segment_size);
+                    let actual = pool.install(|| approach.aggregate_with(&input, OnError::Report));
+                    assert_eq!(
+                        actual, expected,
+                        "{} lanes, {} byte segments",
+                        lanes, segment_size
+                    );
+                    let actual = pool.install(|| approach.aggregate(&input));
+                    assert_eq!(
+                        actual,
+                        Err(first_error),
+                        "{} lanes, {} byte segments",
+                        lanes,
+                        segment_size
+                    );
+                }
+            }
+        }
+    }
+}
@@ -1,76 +1,56 @@
 use std::borrow::Cow;
-use std::path::Path;
-use std::simd::cmp::SimdPartialEq;
-use std::simd::u8x32;
 
 use hashbrown::HashMap;
 
-struct Data {
-    min: f64,
-    max: f64,
-    sum: f64,
-    count: u64,
-}
+use crate::aggregator::Aggregator;
+use crate::delim::find32;
+use crate::error::{ErrorSink, ParseError};
+use crate::parse::{parse_record, station_name};
+use crate::scan::split_line;
+use crate::stats::{StationStats, Stations};
 
-pub fn run(path: &Path) {
-    let file = std::fs::File::open(path).unwrap();
-    let mmap = unsafe { memmap2::Mmap::map(&file).unwrap() };
-    let mut offset = Some(0);
-    let mut result: HashMap<Cow<str>, Data> = HashMap::new();
-    while let Some(o) = &offset {
-        if *o >= mmap.len() {
-            break;
-        }
-        let (key, val, next_offset) = next(&mmap, *o);
-        offset = next_offset;
-        let key = Cow::Borrowed(unsafe { std::str::from_utf8_unchecked(key) });
-        let value = unsafe { std::str::from_utf8_unchecked(val) }.parse::<f64>().unwrap();
-        result.entry(key)
-            .and_modify(|e: &mut _| {
-                e.min = e.min.min(value);
-                e.max = e.max.max(value);
-                e.sum += value;
-                e.count += 1;
-            })
-            .or_insert_with(|| Data {
-                min: value,
-                max: value,
-                sum: value,
-                count: 1,
-            });
-    }
-}
+pub struct Approach;
 
-#[inline]
-fn fill_by_slice(data: &[u8]) -> u8x32 {
-    let mut result = [0; 32];
-    unsafe {
-        core::ptr::copy_nonoverlapping(data.as_ptr(), result.as_mut_ptr(), data.len().min(32));
+impl Aggregator for Approach {
+    fn try_aggregate(&self, input: &[u8], errors: &mut ErrorSink) -> Result<Stations, ParseError> {
+        let mut offset = Some(0);
+        let mut result: HashMap<Cow<str>, StationStats> = HashMap::new();
+        while let Some(o) = &offset {
+            if *o >= input.len() {
+                break;
+            }
+            let (key, val, next_offset) = next(input, *o);
+            offset = next_offset;
+            let Some(value) = errors.check(key, parse_record(key, val))? else {
+                continue;
+            };
+            let Some(key) = errors.check(key, station_name(key))? else {
+                continue;
+            };
+            let key = Cow::Borrowed(key);
+            result.entry(key)
+                .and_modify(|e: &mut _| e.update(value))
+                .or_insert_with(|| StationStats::new(value));
+        }
+        Ok(result
+            .into_iter()
+            .map(|(key, data)| (key.into_owned(), data))
+            .collect())
     }
-    u8x32::from_slice(&result)
 }
 
-fn next<'a>(mmap: &'a memmap2::Mmap, offset: usize) -> (&'a [u8], &'a [u8], Option<usize>) {
-    let mut start = offset;
-    let linefeed = fill_by_slice(&mmap[start..]).simd_eq(u8x32::splat(0x0A)).first_set();
-    let semicolon = fill_by_slice(&mmap[start..]).simd_eq(u8x32::splat(0x3B)).first_set();
+fn next(mmap: &[u8], offset: usize) -> (&[u8], Option<&[u8]>, Option<usize>) {
+    let start = offset;
+    let linefeed = find32(&mmap[start..], b'\n');
+    let semicolon = find32(&mmap[start..], b';');
     match (linefeed, semicolon) {
-        (Some(l), Some(s)) => {
-            (&mmap[start..start + s], &mmap[start + s + 1..start + l], Some(start + l + 1))
-        }
-        (Some(l), None) => {
-            unreachable!()
+        (Some(l), Some(s)) if s < l => {
+            (&mmap[start..start + s], Some(&mmap[start + s + 1..start + l]), Some(start + l + 1))
         }
-        (None, Some(s)) => {
-            for i in 32..mmap.len() {
-                if mmap[start + i] == 0x0A {
-                    return (&mmap[start..start + s], &mmap[start + s + 1..start + i], Some(start + i + 1));
-                }
-            }
-            (&mmap[start..], &mmap[start + s + 1..], None)
-        }
-        (None, None) => {
-            (&mmap[start..], &mmap[start..], None)
+        (Some(l), _) => {
+            (&mmap[start..start + l], None, Some(start + l + 1))
         }
+        // The line does not fit the window; long names and the final line end up here.
+        (None, _) => split_line(mmap, start),
     }
-}
\ No newline at end of file
+}
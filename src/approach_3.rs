@@ -1,112 +1,94 @@
-use std::borrow::Cow;
-use std::path::Path;
-use std::simd::cmp::SimdPartialEq;
-use std::simd::u8x32;
+use std::borrow::{Borrow, Cow};
+use std::hash::Hash;
 
 use hashbrown::HashMap;
-use rayon::prelude::*;
 
-#[derive(Debug)]
-struct Data {
-    min: f64,
-    max: f64,
-    sum: f64,
-    count: u64,
-}
+use crate::aggregator::Aggregator;
+use crate::delim::find32;
+use crate::error::{Aggregate, Error, ErrorSink, OnError, ParseError};
+use crate::input::Input;
+use crate::merge::merge_maps;
+use crate::parse::{parse_record, station_name};
+use crate::scan::split_line;
+use crate::schedule::{self, SEGMENT_SIZE};
+use crate::stats::{StationStats, Stations};
 
-pub fn run(path: &Path) {
-    let file = std::fs::File::open(path).unwrap();
-    let mmap = unsafe { memmap2::Mmap::map(&file).unwrap() };
-    let cpu_count = std::thread::available_parallelism().unwrap();
-    let parallel_count = usize::from(cpu_count) * 1;
-    let chunk_size = mmap.len() / parallel_count;
-    let result = (0..parallel_count)
-        .into_iter()
-        .map(|pos| optimize_position(&mmap, pos * chunk_size))
-        .collect::<Vec<_>>()
-        .par_iter()
-        .map(|&offset| {
-            let mut offset = Some(offset);
-            let mut result: HashMap<Cow<str>, Data> = HashMap::new();
-            while let Some(o) = &offset {
-                if *o >= mmap.len() {
-                    break;
-                }
-                let (key, val, next_offset) = next(&mmap, *o);
-                offset = next_offset;
-                let key = Cow::Borrowed(unsafe { std::str::from_utf8_unchecked(key) });
-                let value = unsafe { std::str::from_utf8_unchecked(val) }.parse::<f64>().unwrap();
-                result.entry(key)
-                    .and_modify(|e: &mut _| {
-                        e.min = e.min.min(value);
-                        e.max = e.max.max(value);
-                        e.sum += value;
-                        e.count += 1;
-                    })
-                    .or_insert_with(|| Data {
-                        min: value,
-                        max: value,
-                        sum: value,
-                        count: 1,
-                    });
-            }
-            result
-        })
-        .reduce(HashMap::<Cow<str>, Data>::new, |mut acc, x| {
-            for (key, value) in x {
-                acc.entry(key)
-                    .and_modify(|e| {
-                        e.min = e.min.min(value.min);
-                        e.max = e.max.max(value.max);
-                        e.sum += value.sum;
-                        e.count += value.count;
-                    })
-                    .or_insert(value);
-            }
-            acc
-        });
-    // println!("{:#?}", result);
+pub struct Approach;
+
+impl Aggregator for Approach {
+    fn try_aggregate(&self, input: &[u8], errors: &mut ErrorSink) -> Result<Stations, ParseError> {
+        let result = schedule::run(input, SEGMENT_SIZE, errors, HashMap::new, step, merge)?;
+        Ok(result
+            .into_iter()
+            .map(|(key, data): (Cow<str>, _)| (key.into_owned(), data))
+            .collect())
+    }
+
+    /// Reads files segment by segment on the workers that scan them. Segments only live
+    /// until the next one is read, so names are copied into the tables.
+    fn aggregate_input(&self, input: &Input, on_error: OnError) -> Result<Aggregate, Error> {
+        let step = |segment: &[u8], result: &mut HashMap<String, _>, errors: &mut _| step(segment, result, errors);
+        let result = schedule::run_input(input, SEGMENT_SIZE, on_error, HashMap::new, step, merge)?;
+        Ok(result.map(|stations| stations.into_iter().collect()))
+    }
+
+    fn preads(&self) -> bool {
+        true
+    }
 }
 
-#[inline]
-fn optimize_position<'a>(mmap: &'a memmap2::Mmap, position: usize) -> usize {
-    mmap[position..]
-        .iter()
-        .position(|&x| x == b'\n')
-        .map(|x| position + x + 1)
-        .unwrap_or_else(|| mmap.len())
+fn merge<K>(maps: Vec<HashMap<K, StationStats>>) -> HashMap<K, StationStats>
+where
+    K: Borrow<str> + Hash + Eq + Clone + Send + Sync,
+{
+    merge_maps(maps, StationStats::merge)
 }
 
-#[inline]
-fn fill_by_slice(data: &[u8]) -> u8x32 {
-    let mut result = [0; 32];
-    unsafe {
-        core::ptr::copy_nonoverlapping(data.as_ptr(), result.as_mut_ptr(), data.len().min(32));
+/// Scans a segment into `result`, whose keys either borrow names from the input or own
+/// them.
+fn step<'a, K>(
+    input: &'a [u8],
+    result: &mut HashMap<K, StationStats>,
+    errors: &mut ErrorSink,
+) -> Result<(), ParseError>
+where
+    K: Borrow<str> + Hash + Eq + From<&'a str>,
+{
+    let mut offset = Some(0);
+    while let Some(o) = &offset {
+        if *o >= input.len() {
+            break;
+        }
+        let (key, val, next_offset) = next(input, *o);
+        offset = next_offset;
+        let Some(value) = errors.check(key, parse_record(key, val))? else {
+            continue;
+        };
+        let Some(key) = errors.check(key, station_name(key))? else {
+            continue;
+        };
+        match result.get_mut(key) {
+            Some(stats) => stats.update(value),
+            None => {
+                result.insert(K::from(key), StationStats::new(value));
+            }
+        }
     }
-    u8x32::from_slice(&result)
+    Ok(())
 }
 
-fn next<'a>(mmap: &'a memmap2::Mmap, offset: usize) -> (&'a [u8], &'a [u8], Option<usize>) {
-    let mut start = offset;
-    let linefeed = fill_by_slice(&mmap[start..]).simd_eq(u8x32::splat(0x0A)).first_set();
-    let semicolon = fill_by_slice(&mmap[start..]).simd_eq(u8x32::splat(0x3B)).first_set();
+fn next(mmap: &[u8], offset: usize) -> (&[u8], Option<&[u8]>, Option<usize>) {
+    let start = offset;
+    let linefeed = find32(&mmap[start..], b'\n');
+    let semicolon = find32(&mmap[start..], b';');
     match (linefeed, semicolon) {
-        (Some(l), Some(s)) => {
-            (&mmap[start..start + s], &mmap[start + s + 1..start + l], Some(start + l + 1))
-        }
-        (Some(l), None) => {
-            unreachable!()
-        }
-        (None, Some(s)) => {
-            for i in 32..mmap.len() {
-                if mmap[start + i] == 0x0A {
-                    return (&mmap[start..start + s], &mmap[start + s + 1..start + i], Some(start + i + 1));
-                }
-            }
-            (&mmap[start..], &mmap[start + s + 1..], None)
+        (Some(l), Some(s)) if s < l => {
+            (&mmap[start..start + s], Some(&mmap[start + s + 1..start + l]), Some(start + l + 1))
         }
-        (None, None) => {
-            (&mmap[start..], &mmap[start..], None)
+        (Some(l), _) => {
+            (&mmap[start..start + l], None, Some(start + l + 1))
         }
+        // The line does not fit the window; long names and the final line end up here.
+        (None, _) => split_line(mmap, start),
     }
 }
\ No newline at end of file
@@ -1,107 +1,99 @@
-use std::borrow::Cow;
-use std::hash::{Hash, Hasher};
-use std::path::Path;
-use std::simd::cmp::SimdPartialEq;
-use std::simd::u8x32;
-
-use hashbrown::HashMap;
-use rayon::prelude::*;
-
-#[derive(Debug)]
-struct Data<'s> {
-    key: &'s str,
-    min: f64,
-    max: f64,
-    sum: f64,
-    count: u64,
-}
+use crate::aggregator::Aggregator;
+use crate::delim::{find, find32};
+use crate::error::{ErrorSink, ParseError};
+use crate::parse::parse_record;
+use crate::partition::line_start;
+use crate::stats::{StationStats, Stations};
+use crate::table::{hash_key, StationTable};
 
 const TABLE_SIZE: usize = 1 << 17;
 const SEGMENT_SIZE: usize = 1 << 21;
 
-pub fn run(path: &Path) {
-    let file = std::fs::File::open(path).unwrap();
-    let mmap = unsafe { memmap2::Mmap::map(&file).unwrap() };
+pub struct Approach;
 
-    let mut offset = Some(0);
-    let mut result: Vec<Option<Data>> = Vec::with_capacity(TABLE_SIZE);
-    for _ in 0..TABLE_SIZE {
-        result.push(None);
-    }
+impl Aggregator for Approach {
+    fn try_aggregate(&self, input: &[u8], errors: &mut ErrorSink) -> Result<Stations, ParseError> {
+        let mut offset = Some(0);
+        let mut result = StationTable::with_capacity(TABLE_SIZE);
 
-    while let Some(o) = &offset {
-        if *o >= mmap.len() {
-            break;
+        while let Some(o) = &offset {
+            if *o >= input.len() {
+                break;
+            }
+            offset = step(input, *o, &mut result, errors)?;
         }
-        offset = step(&mmap, *o, &mut result);
-    }
-}
-
-#[inline]
-fn fill_by_slice(data: &[u8]) -> u8x32 {
-    if data.len() > 32 {
-        return u8x32::from_slice(data);
-    }
-    let mut result = [0; 32];
-    unsafe {
-        core::ptr::copy_nonoverlapping(data.as_ptr(), result.as_mut_ptr(), data.len());
+        Ok(result.to_stations())
     }
-    return u8x32::from_slice(&result);
 }
 
-
-fn step<'a: 'b, 'b>(mmap: &'a memmap2::Mmap, offset: usize, result: &'b mut Vec<Option<Data<'a>>>) -> Option<usize> {
-    let (segment, next) = segmentation(&mmap, offset);
+fn step(
+    mmap: &[u8],
+    offset: usize,
+    result: &mut StationTable<StationStats>,
+    errors: &mut ErrorSink,
+) -> Result<Option<usize>, ParseError> {
+    let (segment, next) = segmentation(mmap, offset);
     let (a, b, c) = split3_segment(segment);
     let mut offset_a = 0;
     let mut offset_b = 0;
     let mut offset_c = 0;
-    while true {
+    loop {
         let temp_a = next_line(a, offset_a);
         let temp_b = next_line(b, offset_b);
         let temp_c = next_line(c, offset_c);
-        if let Some((key_a, value_a, next_offset_a)) = temp_a {
-            do_line(key_a, value_a, result);
+        if let Some((line_a, next_offset_a)) = temp_a {
+            do_line(line_a, result, errors)?;
             offset_a = next_offset_a;
         } else {
             break;
         }
-        if let Some((key_b, value_b, next_offset_b)) = temp_b {
-            do_line(key_b, value_b, result);
+        if let Some((line_b, next_offset_b)) = temp_b {
+            if let Err(err) = do_line(line_b, result, errors) {
+                // Report the earliest bad line, as a sequential scan would.
+                return Err(drain(a, offset_a, result, errors).err().unwrap_or(err));
+            }
             offset_b = next_offset_b;
         } else {
             break;
         }
-        if let Some((key_c, value_c, next_offset_c)) = temp_c {
-            do_line(key_c, value_c, result);
+        if let Some((line_c, next_offset_c)) = temp_c {
+            if let Err(err) = do_line(line_c, result, errors) {
+                let earlier = drain(a, offset_a, result, errors)
+                    .and_then(|_| drain(b, offset_b, result, errors));
+                return Err(earlier.err().unwrap_or(err));
+            }
             offset_c = next_offset_c;
         } else {
             break;
         }
     }
 
-    while let Some((key, value, next_offset)) = next_line(a, offset_a) {
-        do_line(key, value, result);
-        offset_a = next_offset;
-    }
-    while let Some((key, value, next_offset)) = next_line(b, offset_b) {
-        do_line(key, value, result);
-        offset_b = next_offset;
-    }
-    while let Some((key, value, next_offset)) = next_line(c, offset_c) {
-        do_line(key, value, result);
-        offset_c = next_offset;
-    }
+    drain(a, offset_a, result, errors)?;
+    drain(b, offset_b, result, errors)?;
+    drain(c, offset_c, result, errors)?;
 
     if next < mmap.len() {
-        return Some(next);
+        return Ok(Some(next));
     }
-    None
+    Ok(None)
 }
 
+/// Processes the rest of one stream on its own.
+fn drain(
+    stream: &[u8],
+    mut offset: usize,
+    result: &mut StationTable<StationStats>,
+    errors: &mut ErrorSink,
+) -> Result<(), ParseError> {
+    while let Some((line, next_offset)) = next_line(stream, offset) {
+        do_line(line, result, errors)?;
+        offset = next_offset;
+    }
+    Ok(())
+}
 
 #[inline]
-fn segmentation<'a>(mmap: &'a memmap2::Mmap, offset: usize) -> (&'a [u8], usize) {
+fn segmentation(mmap: &[u8], offset: usize) -> (&[u8], usize) {
     let start = offset;
     if start + SEGMENT_SIZE < mmap.len() {
         for end in (start..(start + SEGMENT_SIZE).min(mmap.len())).rev() {
@@ -115,68 +107,52 @@ fn segmentation<'a>(mmap: &'a memmap2::Mmap, offset: usize) -> (&'a [u8], usize)
 
 #[inline]
 fn split3_segment(segment: &[u8]) -> (&[u8], &[u8], &[u8]) {
-    let mut segend_a = segment.len() / 3;
-    let mut segend_b = segment.len() / 3 * 2;
-
-    while segend_a > 0 && segment[segend_a] != b'\n' {
-        segend_a -= 1;
-    }
-    while segend_b > 0 && segment[segend_b] != b'\n' {
-        segend_b -= 1;
-    }
+    let segend_a = line_start(segment, segment.len() / 3);
+    let segend_b = line_start(segment, segment.len() / 3 * 2).max(segend_a);
 
     let a = &segment[..segend_a];
-    let b = &segment[segend_a + 1..segend_b];
-    let c = &segment[segend_b + 1..];
+    let b = &segment[segend_a..segend_b];
+    let c = &segment[segend_b..];
 
     (a, b, c)
 }
 
+type Line<'a> = (&'a [u8], Option<&'a [u8]>);
+
 #[inline]
-fn next_line<'a>(segment: &'a [u8], start: usize) -> Option<(&'a str, f64, usize)> {
+fn next_line(segment: &[u8], start: usize) -> Option<(Line<'_>, usize)> {
     if start >= segment.len() {
         return None;
     }
 
     let semicolon = find_pattern(segment, start, 0x3B);
     let linefeed = find_pattern(segment, start, 0x0A);
-    let key = unsafe { std::str::from_utf8_unchecked(&segment[start..semicolon]) };
-    let value = fast_float::parse::<f64, _>(&segment[semicolon + 1..linefeed]).unwrap();
-    Some((key, value, linefeed + 1))
+    if semicolon >= linefeed {
+        return Some(((&segment[start..linefeed], None), linefeed + 1));
+    }
+    let line = (&segment[start..semicolon], Some(&segment[semicolon + 1..linefeed]));
+    Some((line, linefeed + 1))
 }
 
 #[inline]
-fn do_line<'a: 'b, 'b>(key: &'a str, val: f64, result: &'b mut Vec<Option<Data<'a>>>) -> Option<(&'a str, f64, usize)> {
-    let mut hasher = ahash::AHasher::default();
-    key.hash(&mut hasher);
-    let idx = hasher.finish() as usize % TABLE_SIZE;
-    let entry = &mut result[idx];
-    if let Some(entry) = entry {
-        entry.min = entry.min.min(val);
-        entry.max = entry.max.max(val);
-        entry.sum += val;
-        entry.count += 1;
-    } else {
-        result[idx] = Some(Data {
-            key: key,
-            min: val,
-            max: val,
-            sum: val,
-            count: 1,
-        });
-    }
-    None
+fn do_line(
+    (key, val): Line<'_>,
+    result: &mut StationTable<StationStats>,
+    errors: &mut ErrorSink,
+) -> Result<(), ParseError> {
+    if let Some(value) = errors.check(key, parse_record(key, val))? {
+        errors.check(key, result.update(key, hash_key(key), value))?;
+    }
+    Ok(())
 }
 
 #[inline]
 fn find_pattern(segment: &[u8], start: usize, pattern: u8) -> usize {
-    if let Some(pos) = fill_by_slice(&segment[start..]).simd_eq(u8x32::splat(pattern)).first_set() {
+    let rest = &segment[start..];
+    if let Some(pos) = find32(rest, pattern) {
         return start + pos;
     }
-    for i in (start + 32)..segment.len() {
-        if segment[i] == pattern {
-            return i;
-        }
-    }
-    segment.len()
+    rest.get(32..)
+        .and_then(|rest| find(rest, pattern))
+        .map_or(segment.len(), |pos| start + 32 + pos)
 }
\ No newline at end of file
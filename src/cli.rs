@@ -0,0 +1,340 @@
+use std::path::PathBuf;
+
+use regex::Regex;
+
+use rs_1brc::approach_11::MAX_LANES;
+use rs_1brc::generate::StationSet;
+use rs_1brc::histogram::DEFAULT_PERCENTILES;
+use rs_1brc::input::Backend;
+use rs_1brc::report::Format;
+use rs_1brc::{OnError, StationFilter};
+
+pub const USAGE: &str = "\
+usage: rs-1brc [options] <input>...   (files, directories or globs; `-` reads stdin)
+       rs-1brc generate [generate options] <rows> <output>
+       rs-1brc merge [merge options] <snapshot>...
+
+options:
+  -a, --approach <0..11|best> aggregation approach to run (default: best)
+  -l, --lanes <N>             interleaved lanes per segment of approach 11, 1 to 8 (default: 3)
+  -r, --repeat <N>            run the aggregation N times and report the mean time (default: 1)
+  -t, --threads <N>           number of worker threads (default: available parallelism)
+  -o, --output <format>       text, lines, csv or json (default: text)
+  -d, --stddev                add the standard deviation to text and lines output (csv and json always have it)
+  -e, --on-error <policy>     on a malformed line: fail, skip it, or report it to stderr (default: fail)
+  -i, --io <backend>          mmap the input or read it with parallel preads, which approaches 3, 9
+                              and 11 support (default: mmap)
+  -w, --workers               print per-worker segment counts to stderr (approaches 3, 9, 11)
+  -n, --rows                  print the well-formed rows of the selected stations in every input file
+                              to stderr
+  -H, --histogram             keep a histogram per station and report its median, p90 and p99
+  -p, --percentile <P,...>    also report these percentiles, 0 to 100 (implies --histogram without --sketch)
+  -S, --sketch                like --histogram, but with approximate quantile and distinct-value sketches,
+                              for readings in any decimal form
+      --station <name>        only aggregate this station; repeat for more
+      --prefix <prefix>       only aggregate stations whose name starts with <prefix>
+      --station-regex <re>    only aggregate stations whose name matches <re>
+      --allow-list <path>     only aggregate the stations named in <path>, one per line
+                              (filters add up; any of them selects a station; approach 9 only)
+      --save <path>           also write the result to <path> as a binary snapshot
+  -q, --quiet                 do not print timings to stderr
+  -h, --help                  print this message
+
+generate options:
+  <rows> accepts a K, M or G suffix, e.g. `1G` for the billion-row file.
+  -s, --seed <N>              seed of the random number generator (default: 0)
+      --10k                   use 10,000 distinct station names instead of the official 413
+  -t, --threads <N>           number of worker threads (default: available parallelism)
+  -q, --quiet                 do not print timings to stderr
+
+merge options:
+  Combines snapshots written by `--save`, e.g. on different machines, into one result.
+  -o, --output <format>       text, lines, csv or json (default: text)
+  -d, --stddev                add the standard deviation to text and lines output
+      --save <path>           also write the merged result to <path> as a snapshot";
+
+#[derive(Debug)]
+pub enum Command {
+    Run(Args),
+    Generate(GenerateArgs),
+    Merge(MergeArgs),
+    Help,
+}
+
+#[derive(Debug)]
+pub struct Args {
+    /// Files, directories or globs; several of them are aggregated with approach 9.
+    pub inputs: Vec<PathBuf>,
+    pub approach: String,
+    pub lanes: Option<usize>,
+    pub repeat: usize,
+    pub threads: Option<usize>,
+    pub output: Format,
+    pub stddev: bool,
+    pub on_error: OnError,
+    pub io: Backend,
+    pub workers: bool,
+    pub rows: bool,
+    /// Percentiles to report from per-station histograms; `None` keeps plain statistics.
+    pub percentiles: Option<Vec<f64>>,
+    /// Report the percentiles and distinct readings from sketches instead of histograms.
+    pub sketch: bool,
+    /// Stations given by `--station`, `--prefix` and `--station-regex`.
+    pub filter: StationFilter,
+    /// Files of further station names, read before aggregating.
+    pub allow_lists: Vec<PathBuf>,
+    pub save: Option<PathBuf>,
+    pub quiet: bool,
+}
+
+#[derive(Debug)]
+pub struct GenerateArgs {
+    pub rows: u64,
+    pub output: PathBuf,
+    pub seed: u64,
+    pub stations: StationSet,
+    pub threads: Option<usize>,
+    pub quiet: bool,
+}
+
+#[derive(Debug)]
+pub struct MergeArgs {
+    pub snapshots: Vec<PathBuf>,
+    pub output: Format,
+    pub stddev: bool,
+    pub save: Option<PathBuf>,
+}
+
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut args = args.into_iter().peekable();
+    if args.peek().map(String::as_str) == Some("generate") {
+        args.next();
+        return parse_generate(args);
+    }
+    if args.peek().map(String::as_str) == Some("merge") {
+        args.next();
+        return parse_merge(args);
+    }
+
+    let mut inputs = Vec::new();
+    let mut approach = "best".to_string();
+    let mut lanes = None;
+    let mut repeat = 1;
+    let mut threads = None;
+    let mut output = Format::default();
+    let mut stddev = false;
+    let mut on_error = OnError::default();
+    let mut io = Backend::default();
+    let mut workers = false;
+    let mut rows = false;
+    let mut histogram = false;
+    let mut sketch = false;
+    let mut percentiles = DEFAULT_PERCENTILES.to_vec();
+    let mut filter = StationFilter::default();
+    let mut allow_lists = Vec::new();
+    let mut save = None;
+    let mut quiet = false;
+
+    while let Some(arg) = args.next() {
+        let (flag, inline) = split_flag(&arg);
+        let mut value = |name: &str| {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("missing value for `{}`", name))
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-q" | "--quiet" => quiet = true,
+            "-w" | "--workers" => workers = true,
+            "-n" | "--rows" => rows = true,
+            "-H" | "--histogram" => histogram = true,
+            "-S" | "--sketch" => sketch = true,
+            "-p" | "--percentile" => {
+                let value = value(&flag)?;
+                for p in value.split(',') {
+                    let p = percentile(&flag, p)?;
+                    if !percentiles.contains(&p) {
+                        percentiles.push(p);
+                    }
+                }
+                histogram = true;
+            }
+            "-a" | "--approach" => approach = value(&flag)?,
+            "-l" | "--lanes" => {
+                let value = value(&flag)?;
+                match number(&flag, &value)? {
+                    n if n <= MAX_LANES => lanes = Some(n),
+                    _ => return Err(format!("`{}` expects at most {} lanes", flag, MAX_LANES)),
+                }
+            }
+            "-r" | "--repeat" => repeat = number(&flag, &value(&flag)?)?,
+            "-t" | "--threads" => threads = Some(number(&flag, &value(&flag)?)?),
+            "-o" | "--output" => output = value(&flag)?.parse()?,
+            "-d" | "--stddev" => stddev = true,
+            "-e" | "--on-error" => on_error = value(&flag)?.parse()?,
+            "-i" | "--io" => io = value(&flag)?.parse()?,
+            "--station" => filter.names.push(value(&flag)?),
+            "--prefix" => filter.prefixes.push(value(&flag)?),
+            "--station-regex" => {
+                let value = value(&flag)?;
+                let pattern = Regex::new(&value).map_err(|err| format!("`{}` expects a regex: {}", flag, err))?;
+                filter.patterns.push(pattern);
+            }
+            "--allow-list" => allow_lists.push(PathBuf::from(value(&flag)?)),
+            "--save" => save = Some(PathBuf::from(value(&flag)?)),
+            // A lone `-` is stdin.
+            _ if flag.len() > 1 && flag.starts_with('-') => {
+                return Err(format!("unknown option `{}`", flag))
+            }
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    let stdin = inputs.iter().any(|input| input.as_os_str() == "-");
+    if inputs.is_empty() {
+        return Err("missing input file".to_string());
+    }
+    if stdin && inputs.len() > 1 {
+        return Err("stdin cannot be aggregated along with other inputs".to_string());
+    }
+    if stdin && repeat > 1 {
+        return Err("`--repeat` needs an input file, stdin can only be read once".to_string());
+    }
+    if sketch && save.is_some() {
+        return Err("`--save` keeps 1BRC temperatures, which `--sketch` does not read".to_string());
+    }
+    Ok(Command::Run(Args {
+        inputs,
+        approach,
+        lanes,
+        repeat,
+        threads,
+        output,
+        stddev,
+        on_error,
+        io,
+        workers,
+        rows,
+        percentiles: (histogram || sketch).then_some(percentiles),
+        sketch,
+        filter,
+        allow_lists,
+        save,
+        quiet,
+    }))
+}
+
+fn parse_generate<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut positional = Vec::new();
+    let mut seed = 0;
+    let mut stations = StationSet::Official;
+    let mut threads = None;
+    let mut quiet = false;
+
+    while let Some(arg) = args.next() {
+        let (flag, inline) = split_flag(&arg);
+        let mut value = |name: &str| {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("missing value for `{}`", name))
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-q" | "--quiet" => quiet = true,
+            "--10k" => stations = StationSet::Distinct10k,
+            "-s" | "--seed" => {
+                let value = value(&flag)?;
+                seed = value
+                    .parse()
+                    .map_err(|_| format!("`{}` expects an integer, got `{}`", flag, value))?;
+            }
+            "-t" | "--threads" => threads = Some(number(&flag, &value(&flag)?)?),
+            _ if flag.starts_with('-') => return Err(format!("unknown option `{}`", flag)),
+            _ => positional.push(arg),
+        }
+    }
+
+    match <[String; 2]>::try_from(positional) {
+        Ok([rows, output]) => Ok(Command::Generate(GenerateArgs {
+            rows: rows_count(&rows)?,
+            output: PathBuf::from(output),
+            seed,
+            stations,
+            threads,
+            quiet,
+        })),
+        Err(_) => Err("generate expects <rows> and <output>".to_string()),
+    }
+}
+
+fn parse_merge<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut snapshots = Vec::new();
+    let mut output = Format::default();
+    let mut stddev = false;
+    let mut save = None;
+
+    while let Some(arg) = args.next() {
+        let (flag, inline) = split_flag(&arg);
+        let mut value = |name: &str| {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("missing value for `{}`", name))
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-o" | "--output" => output = value(&flag)?.parse()?,
+            "-d" | "--stddev" => stddev = true,
+            "--save" => save = Some(PathBuf::from(value(&flag)?)),
+            _ if flag.starts_with('-') => return Err(format!("unknown option `{}`", flag)),
+            _ => snapshots.push(PathBuf::from(arg)),
+        }
+    }
+
+    if snapshots.is_empty() {
+        return Err("merge expects at least one <snapshot>".to_string());
+    }
+    Ok(Command::Merge(MergeArgs {
+        snapshots,
+        output,
+        stddev,
+        save,
+    }))
+}
+
+fn split_flag(arg: &str) -> (String, Option<String>) {
+    match arg.split_once('=') {
+        Some((flag, value)) if arg.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+        _ => (arg.to_string(), None),
+    }
+}
+
+fn number(flag: &str, value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("`{}` expects a positive integer, got `{}`", flag, value)),
+    }
+}
+
+fn percentile(flag: &str, value: &str) -> Result<f64, String> {
+    match value.trim().parse() {
+        Ok(p) if (0.0..=100.0).contains(&p) => Ok(p),
+        _ => Err(format!("`{}` expects percentiles from 0 to 100, got `{}`", flag, value)),
+    }
+}
+
+fn rows_count(value: &str) -> Result<u64, String> {
+    let (digits, scale) = match value.as_bytes().last() {
+        Some(b'K' | b'k') => (&value[..value.len() - 1], 1_000),
+        Some(b'M' | b'm') => (&value[..value.len() - 1], 1_000_000),
+        Some(b'G' | b'g') => (&value[..value.len() - 1], 1_000_000_000),
+        _ => (value, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .ok_or_else(|| format!("invalid row count `{}`", value))
+}
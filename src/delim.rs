@@ -0,0 +1,397 @@
+//! Delimiter search on stable Rust.
+//!
+//! The scanners look for `;` and `\n` in 32-byte windows. A single window is too little
+//! work to pay for a call through runtime dispatch, so [`mask32`] and [`find32`] use the
+//! best backend the crate is compiled for (SSE2 on every x86-64) and inline into the
+//! scan loop. [`find`] searches whole slices and picks AVX2, SSE2 or the SWAR `u64`
+//! fallback once per call, from what the running CPU supports. [`mask64x2`] serves
+//! block scanners that look for both delimiters in one pass.
+
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Avx2,
+    Sse2,
+    Swar,
+}
+
+/// The backend enabled at compile time.
+#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), target_feature = "avx2"))]
+pub const NATIVE: Backend = Backend::Avx2;
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    target_feature = "sse2",
+    not(target_feature = "avx2")
+))]
+pub const NATIVE: Backend = Backend::Sse2;
+#[cfg(not(all(any(target_arch = "x86", target_arch = "x86_64"), target_feature = "sse2")))]
+pub const NATIVE: Backend = Backend::Swar;
+
+impl Backend {
+    /// The fastest backend the running CPU supports.
+    pub fn detect() -> Self {
+        [Backend::Avx2, Backend::Sse2]
+            .into_iter()
+            .find(|backend| backend.is_supported())
+            .unwrap_or(Backend::Swar)
+    }
+
+    /// Every backend the running CPU supports.
+    pub fn available() -> Vec<Self> {
+        [Backend::Swar, Backend::Sse2, Backend::Avx2]
+            .into_iter()
+            .filter(|backend| backend.is_supported())
+            .collect()
+    }
+
+    #[inline]
+    pub fn is_supported(self) -> bool {
+        match self {
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::Sse2 => is_x86_feature_detected!("sse2"),
+            Backend::Swar => true,
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+
+    /// Bitmask of the positions of `needle` in the first 32 bytes of `haystack`.
+    /// Bytes past the end of `haystack` never match.
+    ///
+    /// Panics if the running CPU does not support the backend.
+    #[inline(always)]
+    pub fn mask32(self, haystack: &[u8], needle: u8) -> u32 {
+        assert!(self == NATIVE || self.is_supported(), "{:?} is not supported", self);
+        if haystack.len() >= 32 {
+            // SAFETY: 32 bytes are readable and the CPU supports the backend.
+            return unsafe { self.mask32_unchecked(haystack.as_ptr(), needle) };
+        }
+        self.mask32_short(haystack, needle)
+    }
+
+    /// The last window of every segment, and of every lane of a segment, is usually
+    /// shorter than 32 bytes, so this runs once per segment or lane, not once per input.
+    #[cold]
+    #[inline(never)]
+    fn mask32_short(self, haystack: &[u8], needle: u8) -> u32 {
+        let mut block = [0; 32];
+        block[..haystack.len()].copy_from_slice(haystack);
+        let valid = (1 << haystack.len()) - 1;
+        unsafe { self.mask32_unchecked(block.as_ptr(), needle) & valid }
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must be valid for 32 bytes and the CPU must support the backend.
+    #[inline(always)]
+    unsafe fn mask32_unchecked(self, ptr: *const u8, needle: u8) -> u32 {
+        match self {
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::Avx2 => x86::mask32_avx2(ptr, needle),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::Sse2 => x86::mask32_sse2(ptr, needle),
+            _ => swar::mask32(ptr, needle),
+        }
+    }
+
+    /// Bitmasks of `a` and of `b` in the first 64 bytes of `haystack`, loading each
+    /// byte once. Bytes past the end of `haystack` never match.
+    ///
+    /// Panics if the running CPU does not support the backend.
+    #[inline(always)]
+    pub fn mask64x2(self, haystack: &[u8], a: u8, b: u8) -> (u64, u64) {
+        assert!(self == NATIVE || self.is_supported(), "{:?} is not supported", self);
+        if haystack.len() >= 64 {
+            // SAFETY: 64 bytes are readable and the CPU supports the backend.
+            return unsafe { self.mask64x2_unchecked(haystack.as_ptr(), a, b) };
+        }
+        self.mask64x2_short(haystack, a, b)
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn mask64x2_short(self, haystack: &[u8], a: u8, b: u8) -> (u64, u64) {
+        let mut block = [0; 64];
+        block[..haystack.len()].copy_from_slice(haystack);
+        let valid = (1 << haystack.len()) - 1;
+        let (a, b) = unsafe { self.mask64x2_unchecked(block.as_ptr(), a, b) };
+        (a & valid, b & valid)
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must be valid for 64 bytes and the CPU must support the backend.
+    #[inline(always)]
+    unsafe fn mask64x2_unchecked(self, ptr: *const u8, a: u8, b: u8) -> (u64, u64) {
+        match self {
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::Avx2 => x86::mask64x2_avx2(ptr, a, b),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::Sse2 => x86::mask64x2_sse2(ptr, a, b),
+            _ => swar::mask64x2(ptr, a, b),
+        }
+    }
+
+    /// Position of the first `needle` in `haystack`.
+    ///
+    /// Panics if the running CPU does not support the backend.
+    pub fn find(self, haystack: &[u8], needle: u8) -> Option<usize> {
+        assert!(self == NATIVE || self.is_supported(), "{:?} is not supported", self);
+        // SAFETY: the CPU supports the backend.
+        let found = unsafe {
+            match self {
+                #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                Backend::Avx2 => x86::find_avx2(haystack, needle),
+                #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                Backend::Sse2 => x86::find_sse2(haystack, needle),
+                _ => swar::find(haystack, needle),
+            }
+        };
+        let tail = haystack.len() / 32 * 32;
+        found.or_else(|| first_set(self.mask32(&haystack[tail..], needle)).map(|i| tail + i))
+    }
+}
+
+/// The backend detected for this process.
+#[inline]
+pub fn backend() -> Backend {
+    static BACKEND: OnceLock<Backend> = OnceLock::new();
+    *BACKEND.get_or_init(Backend::detect)
+}
+
+#[inline(always)]
+pub fn mask32(haystack: &[u8], needle: u8) -> u32 {
+    NATIVE.mask32(haystack, needle)
+}
+
+#[inline(always)]
+pub fn mask64x2(haystack: &[u8], a: u8, b: u8) -> (u64, u64) {
+    NATIVE.mask64x2(haystack, a, b)
+}
+
+/// Position of the first `needle` in the first 32 bytes of `haystack`.
+#[inline(always)]
+pub fn find32(haystack: &[u8], needle: u8) -> Option<usize> {
+    first_set(mask32(haystack, needle))
+}
+
+/// Position of the first `needle` in `haystack`.
+#[inline]
+pub fn find(haystack: &[u8], needle: u8) -> Option<usize> {
+    backend().find(haystack, needle)
+}
+
+/// Number of `needle` bytes in `haystack`.
+pub fn count(haystack: &[u8], needle: u8) -> usize {
+    haystack
+        .chunks(64)
+        .map(|block| mask64x2(block, needle, needle).0.count_ones() as usize)
+        .sum()
+}
+
+#[inline(always)]
+pub fn first_set(mask: u32) -> Option<usize> {
+    (mask != 0).then(|| mask.trailing_zeros() as usize)
+}
+
+/// Searches the whole 32-byte blocks of a slice with one of the `mask32` kernels; the
+/// caller handles the tail.
+macro_rules! find_blocks {
+    ($haystack:expr, $needle:expr, $mask32:path) => {{
+        let haystack: &[u8] = $haystack;
+        let mut offset = 0;
+        let mut found = None;
+        while offset + 32 <= haystack.len() {
+            let mask = $mask32(haystack.as_ptr().add(offset), $needle);
+            if mask != 0 {
+                found = Some(offset + mask.trailing_zeros() as usize);
+                break;
+            }
+            offset += 32;
+        }
+        found
+    }};
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86 {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn mask32_avx2(ptr: *const u8, needle: u8) -> u32 {
+        let block = _mm256_loadu_si256(ptr as *const __m256i);
+        let matches = _mm256_cmpeq_epi8(block, _mm256_set1_epi8(needle as i8));
+        _mm256_movemask_epi8(matches) as u32
+    }
+
+    #[inline]
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn mask32_sse2(ptr: *const u8, needle: u8) -> u32 {
+        let needle = _mm_set1_epi8(needle as i8);
+        let lo = _mm_loadu_si128(ptr as *const __m128i);
+        let hi = _mm_loadu_si128(ptr.add(16) as *const __m128i);
+        let lo = _mm_movemask_epi8(_mm_cmpeq_epi8(lo, needle)) as u32;
+        let hi = _mm_movemask_epi8(_mm_cmpeq_epi8(hi, needle)) as u32;
+        lo | hi << 16
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn mask64x2_avx2(ptr: *const u8, a: u8, b: u8) -> (u64, u64) {
+        let (a, b) = (_mm256_set1_epi8(a as i8), _mm256_set1_epi8(b as i8));
+        let lo = _mm256_loadu_si256(ptr as *const __m256i);
+        let hi = _mm256_loadu_si256(ptr.add(32) as *const __m256i);
+        let mask = |needle| {
+            let lo = _mm256_movemask_epi8(_mm256_cmpeq_epi8(lo, needle)) as u32 as u64;
+            let hi = _mm256_movemask_epi8(_mm256_cmpeq_epi8(hi, needle)) as u32 as u64;
+            lo | hi << 32
+        };
+        (mask(a), mask(b))
+    }
+
+    #[inline]
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn mask64x2_sse2(ptr: *const u8, a: u8, b: u8) -> (u64, u64) {
+        let (a, b) = (_mm_set1_epi8(a as i8), _mm_set1_epi8(b as i8));
+        let blocks = [0, 16, 32, 48].map(|i| _mm_loadu_si128(ptr.add(i) as *const __m128i));
+        let mask = |needle| {
+            blocks.iter().enumerate().fold(0, |mask, (i, &block)| {
+                mask | (_mm_movemask_epi8(_mm_cmpeq_epi8(block, needle)) as u16 as u64) << (i * 16)
+            })
+        };
+        (mask(a), mask(b))
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn find_avx2(haystack: &[u8], needle: u8) -> Option<usize> {
+        find_blocks!(haystack, needle, mask32_avx2)
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn find_sse2(haystack: &[u8], needle: u8) -> Option<usize> {
+        find_blocks!(haystack, needle, mask32_sse2)
+    }
+}
+
+mod swar {
+    const LOW7: u64 = 0x7f7f_7f7f_7f7f_7f7f;
+    const ONES: u64 = 0x0101_0101_0101_0101;
+
+    /// One bit per byte of `word` that equals `needle`, lowest byte first.
+    #[inline(always)]
+    fn mask8(word: u64, needle: u8) -> u32 {
+        let x = word ^ (ONES * needle as u64);
+        // The high bit of each byte ends up set iff the byte of `x` is zero. Unlike
+        // `(x - ONES) & !x & HIGH`, no borrow leaks into the bytes above a match.
+        let zero = !(((x & LOW7) + LOW7) | x | LOW7);
+        // Gathers the eight high bits into the top byte.
+        ((zero >> 7).wrapping_mul(0x0102_0408_1020_4080) >> 56) as u32
+    }
+
+    #[inline(always)]
+    pub unsafe fn mask32(ptr: *const u8, needle: u8) -> u32 {
+        let mut mask = 0;
+        for i in 0..4 {
+            let word = u64::from_le_bytes(ptr.add(i * 8).cast::<[u8; 8]>().read_unaligned());
+            mask |= mask8(word, needle) << (i * 8);
+        }
+        mask
+    }
+
+    #[inline(always)]
+    pub unsafe fn mask64x2(ptr: *const u8, a: u8, b: u8) -> (u64, u64) {
+        let mut masks = (0, 0);
+        for i in 0..8 {
+            let word = u64::from_le_bytes(ptr.add(i * 8).cast::<[u8; 8]>().read_unaligned());
+            masks.0 |= (mask8(word, a) as u64) << (i * 8);
+            masks.1 |= (mask8(word, b) as u64) << (i * 8);
+        }
+        masks
+    }
+
+    pub unsafe fn find(haystack: &[u8], needle: u8) -> Option<usize> {
+        find_blocks!(haystack, needle, mask32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scalar_mask32(haystack: &[u8], needle: u8) -> u32 {
+        haystack
+            .iter()
+            .take(32)
+            .enumerate()
+            .filter(|(_, &b)| b == needle)
+            .fold(0, |mask, (i, _)| mask | 1 << i)
+    }
+
+    #[test]
+    fn backends_agree_with_a_scalar_scan() {
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        for backend in Backend::available() {
+            for len in 0..80 {
+                for _ in 0..50 {
+                    let haystack: Vec<u8> = (0..len)
+                        .map(|_| {
+                            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                            // Mostly delimiters and bytes next to them, plus high bytes.
+                            [b';', b'\n', b':', b'<', 0, 0xff, 0x80, b'a'][(state >> 61) as usize]
+                        })
+                        .collect();
+                    for needle in [b';', b'\n', 0, 0xff] {
+                        let expected = scalar_mask32(&haystack, needle);
+                        assert_eq!(backend.mask32(&haystack, needle), expected, "{:?} {:?}", backend, haystack);
+                        let position = haystack.iter().position(|&b| b == needle);
+                        let wide = |needle| {
+                            haystack
+                                .iter()
+                                .take(64)
+                                .enumerate()
+                                .filter(|(_, &b)| b == needle)
+                                .fold(0u64, |mask, (i, _)| mask | 1 << i)
+                        };
+                        assert_eq!(
+                            backend.mask64x2(&haystack, needle, b'a'),
+                            (wide(needle), wide(b'a')),
+                            "{:?} {:?}",
+                            backend,
+                            haystack
+                        );
+                        assert_eq!(backend.find(&haystack, needle), position, "{:?} {:?}", backend, haystack);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn find_scans_past_the_first_window() {
+        let mut haystack = vec![b'a'; 100];
+        for i in 0..100 {
+            haystack[i] = b';';
+            for backend in Backend::available() {
+                assert_eq!(backend.find(&haystack, b';'), Some(i));
+                assert_eq!(backend.find(&haystack[..i], b';'), None);
+            }
+            haystack[i] = b'a';
+        }
+    }
+
+    #[test]
+    fn count_covers_every_block() {
+        for len in [0, 1, 63, 64, 65, 200] {
+            let haystack: Vec<u8> = (0..len).map(|i| if i % 3 == 0 { b'\n' } else { b'a' }).collect();
+            let expected = haystack.iter().filter(|&&b| b == b'\n').count();
+            assert_eq!(count(&haystack, b'\n'), expected, "{} bytes", len);
+        }
+    }
+}
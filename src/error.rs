@@ -0,0 +1,313 @@
+use std::convert::Infallible;
+use std::fmt::{self, Display};
+use std::io;
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::schedule::WorkerStats;
+use crate::stats::Stations;
+
+/// How many rejected lines `OnError::Report` keeps; the rest are only counted.
+pub const MAX_REPORTED: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    MissingSeparator,
+    BadNumber,
+    BadDecimal,
+    InvalidUtf8,
+    NameTooLong,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    /// Byte offset of the start of the offending line.
+    pub offset: usize,
+    /// 1-based line number.
+    pub line: u64,
+    pub kind: ParseErrorKind,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+impl ParseError {
+    /// Fills in `line` from `offset` by counting the newlines in front of it.
+    pub fn locate(mut self, input: &[u8]) -> Self {
+        self.line = 1 + input[..self.offset].iter().filter(|&&b| b == b'\n').count() as u64;
+        self
+    }
+}
+
+impl Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ParseErrorKind::MissingSeparator => "missing `;` separator",
+            ParseErrorKind::BadNumber => "temperature is not of the form -?d?d.d",
+            ParseErrorKind::BadDecimal => "value is not a finite decimal of the form -?d+(.d+)?",
+            ParseErrorKind::InvalidUtf8 => "station name is not valid UTF-8",
+            ParseErrorKind::NameTooLong => "station name is longer than 100 bytes",
+        })
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {} (byte {}): {}", self.line, self.offset, self.kind)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => err.fmt(f),
+            Error::Parse(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Parse(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::Parse(err)
+    }
+}
+
+/// What to do with a malformed line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnError {
+    /// Stop at the first malformed line.
+    #[default]
+    Fail,
+    /// Drop malformed lines and only count them.
+    Skip,
+    /// Drop malformed lines and keep the first `MAX_REPORTED` of them.
+    Report,
+}
+
+impl FromStr for OnError {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(OnError::Fail),
+            "skip" => Ok(OnError::Skip),
+            "report" => Ok(OnError::Report),
+            _ => Err(format!("unknown error policy `{}`", s)),
+        }
+    }
+}
+
+/// Result of an aggregation that may have dropped malformed lines; `S` is what was
+/// kept per station, [`Stations`] unless a richer mode such as
+/// [`histogram`](crate::histogram) was asked for.
+#[derive(Debug, Clone, Default)]
+pub struct Aggregate<S = Stations> {
+    pub stations: S,
+    /// Rejected lines, ordered by offset; only filled by `OnError::Report`.
+    pub errors: Vec<ParseError>,
+    /// Number of rejected lines.
+    pub rejected: u64,
+    /// One entry per worker of [`schedule::run`](crate::schedule::run); empty for other
+    /// approaches.
+    pub workers: Vec<WorkerStats>,
+}
+
+impl<S> Aggregate<S> {
+    /// Converts the stations, keeping everything else.
+    pub fn map<T, F: FnOnce(S) -> T>(self, f: F) -> Aggregate<T> {
+        Aggregate {
+            stations: f(self.stations),
+            errors: self.errors,
+            rejected: self.rejected,
+            workers: self.workers,
+        }
+    }
+}
+
+/// Worker telemetry differs from run to run and is not part of the result.
+impl<S: PartialEq> PartialEq for Aggregate<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.stations == other.stations
+            && self.errors == other.errors
+            && self.rejected == other.rejected
+    }
+}
+
+/// Collects malformed lines according to an [`OnError`] policy.
+///
+/// Offsets are recovered from the position of the line slice inside the input, so
+/// scanners working on chunks or segments need not carry a base offset around. Forked
+/// sinks also carry the telemetry of their worker back to the [`Aggregate`].
+#[derive(Debug)]
+pub struct ErrorSink {
+    policy: OnError,
+    base: usize,
+    errors: Vec<ParseError>,
+    rejected: u64,
+    workers: Vec<WorkerStats>,
+}
+
+impl ErrorSink {
+    pub fn new(policy: OnError, input: &[u8]) -> Self {
+        ErrorSink {
+            policy,
+            base: input.as_ptr() as usize,
+            errors: Vec::new(),
+            rejected: 0,
+            workers: Vec::new(),
+        }
+    }
+
+    /// An empty sink for a worker scanning part of the same input.
+    pub fn fork(&self) -> Self {
+        ErrorSink {
+            policy: self.policy,
+            base: self.base,
+            errors: Vec::new(),
+            rejected: 0,
+            workers: Vec::new(),
+        }
+    }
+
+    /// Passes a successfully parsed value through, or records the error of `line`.
+    /// Returns `Ok(None)` when the line must be skipped.
+    #[inline]
+    pub fn check<T>(&mut self, line: &[u8], result: Result<T, ParseErrorKind>) -> Result<Option<T>, ParseError> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(kind) => self.reject(line, kind).map(|_| None),
+        }
+    }
+
+    /// Records an error for the line starting at `line`, which must point into the input.
+    #[cold]
+    pub fn reject(&mut self, line: &[u8], kind: ParseErrorKind) -> Result<(), ParseError> {
+        self.reject_at(self.offset(line), kind)
+    }
+
+    /// Byte offset of `line` in the input, which it must point into.
+    #[inline]
+    pub fn offset(&self, line: &[u8]) -> usize {
+        (line.as_ptr() as usize).wrapping_sub(self.base)
+    }
+
+    /// Makes lines in `segment`, a copy of the input from `offset` on, count as lines of
+    /// the input, for segments read into a buffer rather than borrowed from the input.
+    pub fn rebase(&mut self, segment: &[u8], offset: usize) {
+        self.base = (segment.as_ptr() as usize).wrapping_sub(offset);
+    }
+
+    #[cold]
+    pub fn reject_at(&mut self, offset: usize, kind: ParseErrorKind) -> Result<(), ParseError> {
+        let error = ParseError {
+            offset,
+            line: 0,
+            kind,
+        };
+        match self.policy {
+            OnError::Fail => return Err(error),
+            OnError::Skip => {}
+            OnError::Report => {
+                if self.errors.len() < MAX_REPORTED {
+                    self.errors.push(error);
+                }
+            }
+        }
+        self.rejected += 1;
+        Ok(())
+    }
+
+    /// Records the telemetry of a finished worker.
+    pub fn record(&mut self, stats: WorkerStats) {
+        self.workers.push(stats);
+    }
+
+    pub fn merge(&mut self, other: ErrorSink) {
+        self.errors.extend(other.errors);
+        self.rejected += other.rejected;
+        self.workers.extend(other.workers);
+    }
+
+    pub fn finish<S>(self, input: &[u8], stations: S) -> Aggregate<S> {
+        let count = |range: Range<usize>| Ok::<_, Infallible>(input[range].iter().filter(|&&b| b == b'\n').count() as u64);
+        let Ok(aggregate) = self.finish_by(count, stations);
+        aggregate
+    }
+
+    /// Like [`finish`](Self::finish) for an input that is not in memory, with `count`
+    /// giving the number of `\n` in a range of it.
+    pub fn finish_by<S, E, F>(mut self, mut count: F, stations: S) -> Result<Aggregate<S>, E>
+    where
+        F: FnMut(Range<usize>) -> Result<u64, E>,
+    {
+        self.errors.sort_unstable_by_key(|error| error.offset);
+        self.errors.truncate(MAX_REPORTED);
+        let mut line = 1;
+        let mut position = 0;
+        for error in &mut self.errors {
+            line += count(position..error.offset)?;
+            position = error.offset;
+            error.line = line;
+        }
+        Ok(Aggregate {
+            stations,
+            errors: self.errors,
+            rejected: self.rejected,
+            workers: self.workers,
+        })
+    }
+}
+
+/// Combines the results of two workers, keeping the error closest to the start of the
+/// input so that parallel scans fail the same way a sequential one would.
+pub fn combine<T, F: FnOnce(T, T) -> T>(
+    a: Result<T, ParseError>,
+    b: Result<T, ParseError>,
+    f: F,
+) -> Result<T, ParseError> {
+    match (a, b) {
+        (Ok(a), Ok(b)) => Ok(f(a, b)),
+        (Err(a), Err(b)) => Err(if b.offset < a.offset { b } else { a }),
+        (Err(err), Ok(_)) | (Ok(_), Err(err)) => Err(err),
+    }
+}
+
+/// Collects the tables of parallel workers in order and merges their sinks into `errors`,
+/// or returns the error closest to the start of the input, like [`combine`].
+pub fn gather<T>(
+    workers: Vec<Result<(T, ErrorSink), ParseError>>,
+    errors: &mut ErrorSink,
+) -> Result<Vec<T>, ParseError> {
+    let failed = workers.iter().filter_map(|worker| worker.as_ref().err());
+    if let Some(err) = failed.min_by_key(|err| err.offset) {
+        return Err(*err);
+    }
+    Ok(workers
+        .into_iter()
+        .flatten()
+        .map(|(table, sink)| {
+            errors.merge(sink);
+            table
+        })
+        .collect())
+}
@@ -0,0 +1,289 @@
+//! Aggregating many files in one run.
+//!
+//! Paths are expanded up front into a sorted list of files, every file is opened, and the
+//! segments of all of them are scheduled on the same workers, so a directory of small
+//! hourly files keeps every thread as busy as one large file would.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::approach_9;
+use crate::delim::count;
+use crate::error::{Error, ErrorSink, OnError, ParseError};
+use crate::filter::{Selection, StationFilter};
+use crate::input::{Backend, Input};
+use crate::schedule::{self, WorkerStats, SEGMENT_SIZE};
+use crate::stats::{Reducer, StationStats};
+use crate::table::StationTable;
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub backend: Backend,
+    pub on_error: OnError,
+    /// Count the rows of every file, at the cost of one more pass over each segment.
+    pub rows: bool,
+    /// Stations to aggregate; rows of the others are skipped before their value is
+    /// parsed, and do not count as rows.
+    pub filter: StationFilter,
+}
+
+/// What was read from one file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSummary {
+    pub path: PathBuf,
+    /// Well-formed rows of the selected stations, when [`Options::rows`] asked for them.
+    pub rows: Option<u64>,
+    /// Number of rejected lines.
+    pub rejected: u64,
+    /// Rejected lines, located within this file; only filled by `OnError::Report`.
+    pub errors: Vec<ParseError>,
+}
+
+/// Result of aggregating several files into one set of stations, each kept as a `V`.
+#[derive(Debug, Clone, Default)]
+pub struct FilesAggregate<V = StationStats> {
+    pub stations: BTreeMap<String, V>,
+    /// One entry per file, in the order they were given.
+    pub files: Vec<FileSummary>,
+    pub workers: Vec<WorkerStats>,
+}
+
+/// An error along with the file it happened in.
+#[derive(Debug)]
+pub struct FileError {
+    pub path: PathBuf,
+    pub error: Error,
+}
+
+impl FileError {
+    fn new(path: &Path, error: impl Into<Error>) -> Self {
+        FileError {
+            path: path.to_path_buf(),
+            error: error.into(),
+        }
+    }
+}
+
+impl Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.error)
+    }
+}
+
+impl std::error::Error for FileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Expands `patterns` into the files they name, in order.
+///
+/// A directory stands for every file below it, in sorted order, and `*` and `?` match
+/// within one path component, like a shell glob. Wildcards and directory walks skip names
+/// starting with `.` unless the pattern spells out the dot. A pattern that names no file
+/// is an error.
+pub fn expand<P: AsRef<Path>>(patterns: &[P]) -> Result<Vec<PathBuf>, FileError> {
+    let mut files = Vec::new();
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        let before = files.len();
+        for path in glob(pattern) {
+            walk(&path, &mut files)?;
+        }
+        if files.len() == before {
+            let error = io::Error::new(io::ErrorKind::NotFound, "matches no files");
+            return Err(FileError::new(pattern, error));
+        }
+    }
+    Ok(files)
+}
+
+/// Paths matching `pattern`, or `pattern` itself if it has no wildcards.
+fn glob(pattern: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let name = component.as_os_str();
+        let component = name.as_encoded_bytes();
+        if !component.contains(&b'*') && !component.contains(&b'?') {
+            for path in &mut paths {
+                path.push(name);
+            }
+            continue;
+        }
+        let mut matched = Vec::new();
+        for dir in &paths {
+            let listing = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+            // Anything that cannot be listed simply matches nothing.
+            let Ok(entries) = fs::read_dir(listing) else { continue };
+            let mut names: Vec<_> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.file_name()))
+                .filter(|name| {
+                    let name = name.as_encoded_bytes();
+                    (!name.starts_with(b".") || component.starts_with(b".")) && matches(component, name)
+                })
+                .collect();
+            names.sort();
+            matched.extend(names.into_iter().map(|name| dir.join(name)));
+        }
+        paths = matched;
+    }
+    paths
+}
+
+/// Whether `name` matches a pattern of literal bytes, `?` and `*`.
+fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, and how much of the name it has taken so far.
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+fn walk(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), FileError> {
+    let metadata = fs::metadata(path).map_err(|err| FileError::new(path, err))?;
+    if !metadata.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let entries = fs::read_dir(path).map_err(|err| FileError::new(path, err))?;
+    let mut entries = entries
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(|err| FileError::new(path, err))?;
+    entries.sort();
+    for entry in entries {
+        if !entry.file_name().is_some_and(|name| name.as_encoded_bytes().starts_with(b".")) {
+            walk(&entry, files)?;
+        }
+    }
+    Ok(())
+}
+
+/// Aggregates every file in `paths` into one set of stations with the scanner of
+/// approach 9, scheduling the segments of all files on the same workers.
+///
+/// Fails with the first malformed line of the earliest failing file under
+/// `OnError::Fail`; line numbers and offsets are always within the file.
+pub fn aggregate(paths: &[PathBuf], options: Options) -> Result<FilesAggregate, FileError> {
+    aggregate_as::<StationStats>(paths, options)
+}
+
+/// Like [`aggregate`], reducing every station with any [`Reducer`] such as a
+/// [`Histogram`](crate::histogram::Histogram). Positions of rows carry the index of
+/// their file in `paths`.
+pub fn aggregate_as<R: Reducer>(
+    paths: &[PathBuf],
+    options: Options,
+) -> Result<FilesAggregate<R::Output>, FileError> {
+    let opened = paths
+        .iter()
+        .map(|path| options.backend.open(path).map_err(|err| FileError::new(path, err)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let inputs: Vec<&Input> = opened.iter().collect();
+    let mut sinks: Vec<_> = inputs
+        .iter()
+        .map(|input| ErrorSink::new(options.on_error, input.bytes().unwrap_or_default()))
+        .collect();
+
+    let init = || (approach_9::storage(), vec![0; paths.len()], Selection::new(&options.filter));
+    let work = |index: usize, segment: &[u8], (table, lines, selection): &mut Scan<R>, errors: &mut ErrorSink| {
+        let skipped = selection.skipped;
+        approach_9::step(index, segment, table, selection, errors)?;
+        if options.rows {
+            // The last line of a file may lack its `\n`.
+            let rows = (count(segment, b'\n') + !segment.ends_with(b"\n") as usize) as u64;
+            lines[index] += rows - (selection.skipped - skipped);
+        }
+        Ok(())
+    };
+    let merge = |scans| merge(scans, &options.filter);
+    let result = schedule::run_inputs(&inputs, SEGMENT_SIZE, &mut sinks, init, work, merge);
+    let ((table, lines, _), workers) = result.map_err(|(index, err)| {
+        let err = match err {
+            Error::Parse(err) => inputs[index].locate(err).map_or_else(Error::Io, Error::Parse),
+            err => err,
+        };
+        FileError::new(&paths[index], err)
+    })?;
+
+    let files = paths
+        .iter()
+        .zip(inputs)
+        .zip(sinks)
+        .zip(lines)
+        .map(|(((path, input), sink), lines)| {
+            let aggregate = sink
+                .finish_by(|range| input.count_lines(range), ())
+                .map_err(|err| FileError::new(path, err))?;
+            Ok(FileSummary {
+                path: path.clone(),
+                rows: options.rows.then(|| lines - aggregate.rejected),
+                rejected: aggregate.rejected,
+                errors: aggregate.errors,
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(FilesAggregate {
+        stations: table.to_map(),
+        files,
+        workers,
+    })
+}
+
+/// A worker's table, the lines it saw in every file, and its view of the filter.
+type Scan<'a, V> = (StationTable<V>, Vec<u64>, Selection<'a>);
+
+fn merge<'a, V: Reducer>(scans: Vec<Scan<'a, V>>, filter: &'a StationFilter) -> Scan<'a, V> {
+    let (tables, lines): (Vec<_>, Vec<_>) = scans.into_iter().map(|(table, lines, _)| (table, lines)).unzip();
+    let total = lines.into_iter().reduce(|mut total, lines| {
+        for (total, lines) in total.iter_mut().zip(lines) {
+            *total += lines;
+        }
+        total
+    });
+    (StationTable::merge_all(tables), total.unwrap_or_default(), Selection::new(filter))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_match_like_a_shell() {
+        for (pattern, name, expected) in [
+            ("*", "measurements.txt", true),
+            ("*.txt", "measurements.txt", true),
+            ("*.txt", "measurements.txt.gz", false),
+            ("2024-??-*.txt", "2024-01-31.txt", true),
+            ("2024-??-*.txt", "2024-1-31.txt", false),
+            ("a*b*c", "abxbxc", true),
+            ("a*b*c", "abxbxcx", false),
+            ("", "", true),
+            ("?", "", false),
+        ] {
+            assert_eq!(matches(pattern.as_bytes(), name.as_bytes()), expected, "{} ~ {}", pattern, name);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Aggregating only some stations.
+//!
+//! A [`StationFilter`] is applied inside the scan, right after the `;` of a row is found:
+//! every worker remembers its decision for each station in a table keyed by the hash the
+//! scan computes anyway, so a row of an excluded station costs one table probe and its
+//! value is never parsed. The table starts out with the exact names of the filter, and
+//! prefixes and patterns are matched once per station and worker.
+//!
+//! [`Filtered`](crate::approach_9::Filtered) runs such a scan behind the [`Aggregator`]
+//! interface, for single inputs and streams; [`files::Options`] takes a filter for
+//! several files.
+//!
+//! [`Aggregator`]: crate::Aggregator
+//! [`files::Options`]: crate::files::Options
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::parse::station_name;
+use crate::table::{hash_key, StationTable};
+
+/// Which stations to aggregate: those named exactly, those starting with one of the
+/// prefixes, and those matching one of the patterns anywhere in their name. A filter
+/// without any of them selects every station.
+#[derive(Debug, Clone, Default)]
+pub struct StationFilter {
+    pub names: Vec<String>,
+    pub prefixes: Vec<String>,
+    pub patterns: Vec<Regex>,
+}
+
+impl StationFilter {
+    /// Selects exactly the stations in `names`.
+    pub fn names<I: IntoIterator<Item = S>, S: Into<String>>(names: I) -> Self {
+        StationFilter {
+            names: names.into_iter().map(Into::into).collect(),
+            ..StationFilter::default()
+        }
+    }
+
+    /// Whether the filter selects every station.
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.prefixes.is_empty() && self.patterns.is_empty()
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.is_empty()
+            || self.names.iter().any(|n| n == name)
+            || self.prefixes.iter().any(|prefix| name.starts_with(prefix.as_str()))
+            || self.patterns.iter().any(|pattern| pattern.is_match(name))
+    }
+}
+
+/// Reads an allow-list of station names, one per line; empty lines are ignored.
+pub fn read_names(path: &Path) -> io::Result<Vec<String>> {
+    let text = fs::read_to_string(path)?;
+    Ok(text
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// The decisions of a filter as seen by one worker; `None` when it selects everything.
+pub(crate) struct Selection<'a> {
+    filter: &'a StationFilter,
+    decided: Option<StationTable<bool>>,
+    /// Rows skipped because their station is not selected.
+    pub(crate) skipped: u64,
+}
+
+impl<'a> Selection<'a> {
+    pub(crate) fn new(filter: &'a StationFilter) -> Self {
+        let decided = (!filter.is_empty()).then(|| {
+            let mut decided = StationTable::new();
+            for name in &filter.names {
+                decided.get_or_insert_with(name.as_bytes(), hash_key(name.as_bytes()), || true);
+            }
+            decided
+        });
+        Selection { filter, decided, skipped: 0 }
+    }
+
+    /// Whether rows of the station `key` are aggregated; `hash` must be `hash_key(key)`.
+    /// Names that are not UTF-8 never match a filter.
+    #[inline]
+    pub(crate) fn selects(&mut self, key: &[u8], hash: u64) -> bool {
+        let Some(decided) = &mut self.decided else {
+            return true;
+        };
+        let filter = self.filter;
+        let selected = *decided.get_or_insert_with(key, hash, || station_name(key).is_ok_and(|name| filter.matches(name)));
+        self.skipped += !selected as u64;
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stations_match_any_part_of_the_filter() {
+        let filter = StationFilter {
+            names: vec!["Oslo".to_string()],
+            prefixes: vec!["San ".to_string()],
+            patterns: vec![Regex::new("^Ab|ville$").unwrap()],
+        };
+        let mut selection = Selection::new(&filter);
+        let cases = [
+            ("Oslo", true),
+            ("Oslo ", false),
+            ("San Jose", true),
+            ("Santiago", false),
+            ("Abha", true),
+            ("Brazzaville", true),
+            ("Cabo San Lucas", false),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(filter.matches(name), expected, "{}", name);
+            for _ in 0..2 {
+                assert_eq!(selection.selects(name.as_bytes(), hash_key(name.as_bytes())), expected, "{}", name);
+            }
+        }
+        assert!(!selection.selects(b"\xff", hash_key(b"\xff")));
+        assert!(Selection::new(&StationFilter::default()).selects(b"\xff", hash_key(b"\xff")));
+    }
+}
@@ -0,0 +1,158 @@
+//! Exact per-station quantiles.
+//!
+//! Temperatures are tenths in `[-99.9, 99.9]`, so a station's values fit in 1999 counters
+//! and any quantile can be read off them exactly, in the same single pass that the
+//! regular statistics take. A histogram also determines min, max, sum and count, so it
+//! replaces [`StationStats`] in the table rather than sitting next to it.
+
+use std::collections::BTreeMap;
+
+use crate::error::{Aggregate, OnError, ParseError};
+use crate::reduce;
+use crate::stats::{square, Reducer, StationStats};
+
+/// Lowest temperature, in tenths.
+const MIN: i16 = -999;
+/// One bucket per temperature in tenths.
+pub const BUCKETS: usize = 1999;
+/// Percentiles always reported in histogram mode: the median, p90 and p99.
+pub const DEFAULT_PERCENTILES: [f64; 3] = [50.0, 90.0, 99.0];
+
+/// Histograms keyed by station name, in output order.
+pub type Histograms = BTreeMap<String, Histogram>;
+
+/// How often every temperature was seen at one station; 16 KiB per station and worker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    counts: Box<[u64; BUCKETS]>,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: vec![0; BUCKETS].into_boxed_slice().try_into().unwrap(),
+        }
+    }
+}
+
+impl Reducer for Histogram {
+    type Value = i16;
+    type Output = Histogram;
+
+    fn init() -> Self {
+        Histogram::default()
+    }
+
+    #[inline]
+    fn update(&mut self, value: i16) {
+        self.counts[(value - MIN) as usize] += 1;
+    }
+
+    fn merge(&mut self, other: &Self) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other;
+        }
+    }
+
+    fn finish(&self) -> Histogram {
+        self.clone()
+    }
+}
+
+impl Histogram {
+    /// Number of values seen.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The regular statistics of the values seen.
+    pub fn stats(&self) -> StationStats {
+        self.values().fold(StationStats::default(), |mut stats, (value, count)| {
+            stats.min = stats.min.min(value);
+            stats.max = stats.max.max(value);
+            stats.sum += value as i64 * count as i64;
+            stats.sum_sq += square(value) * count;
+            stats.count += count;
+            stats
+        })
+    }
+
+    /// The `p`th percentile in tenths, by nearest rank: the smallest value that at least
+    /// `p` percent of all values are less than or equal to. It is always a value that
+    /// was seen, so the median of an even number of values is the lower middle one.
+    ///
+    /// Returns `None` for an empty histogram. Panics unless `p` is within `0..=100`.
+    pub fn percentile(&self, p: f64) -> Option<i16> {
+        assert!((0.0..=100.0).contains(&p), "percentile {} is not within 0..=100", p);
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((p * count as f64 / 100.0).ceil() as u64).clamp(1, count);
+        let mut seen = 0;
+        self.values().find_map(|(value, n)| {
+            seen += n;
+            (seen >= rank).then_some(value)
+        })
+    }
+
+    pub fn median(&self) -> Option<i16> {
+        self.percentile(50.0)
+    }
+
+    /// Values that were seen, in increasing order, with how often.
+    fn values(&self) -> impl Iterator<Item = (i16, u64)> + '_ {
+        (MIN..)
+            .zip(self.counts.iter())
+            .filter(|(_, &count)| count > 0)
+            .map(|(value, &count)| (value, count))
+    }
+}
+
+/// Aggregates `input` into per-station histograms with the scanner and scheduling of
+/// approach 9.
+pub fn aggregate_with(input: &[u8], on_error: OnError) -> Result<Aggregate<Histograms>, ParseError> {
+    reduce::aggregate::<Histogram>(input, on_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(values: &[i16]) -> Histogram {
+        let mut histogram = Histogram::default();
+        for &value in values {
+            histogram.update(value);
+        }
+        histogram
+    }
+
+    #[test]
+    fn percentiles_by_nearest_rank() {
+        let five = histogram(&[15, 20, 35, 40, 50]);
+        let expected = [(0.0, 15), (5.0, 15), (30.0, 20), (40.0, 20), (50.0, 35), (100.0, 50)];
+        for (p, value) in expected {
+            assert_eq!(five.percentile(p), Some(value), "p{}", p);
+        }
+
+        let ten = histogram(&(1..=10).collect::<Vec<_>>());
+        assert_eq!(ten.median(), Some(5));
+        assert_eq!(ten.percentile(90.0), Some(9));
+        assert_eq!(ten.percentile(90.1), Some(10));
+        assert_eq!(Histogram::default().median(), None);
+    }
+
+    #[test]
+    fn stats_match_the_values_seen() {
+        let values = [-999, 999, 0, -5, -5, 12];
+        let mut expected = StationStats::default();
+        for &value in &values {
+            expected.update(value);
+        }
+        let mut merged = histogram(&values[..2]);
+        merged.merge(&histogram(&values[2..]));
+        assert_eq!(merged.stats(), expected);
+        assert_eq!(merged.percentile(99.0), Some(999));
+        assert_eq!(merged.percentile(1.0), Some(-999));
+    }
+}
@@ -0,0 +1,349 @@
+use std::alloc::{self, Layout};
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::{Deref, Range};
+use std::path::Path;
+use std::ptr::NonNull;
+use std::str::FromStr;
+
+use memmap2::Mmap;
+
+use crate::delim::count;
+use crate::error::ParseError;
+
+/// Bytes read past the end of a segment to finish its last line; longer lines are read
+/// in further pieces of this size.
+pub const TAIL: usize = 4096;
+/// Alignment of read buffers, a page so that reads land on whole pages.
+const ALIGN: usize = 4096;
+
+/// How the bytes of an input file are brought into memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Maps the file; pages are faulted in on first touch.
+    #[default]
+    Mmap,
+    /// Reads the file with positional reads: every worker of a scheduled run reads the
+    /// segments it claims into its own aligned buffer, so memory stays at one segment
+    /// per thread and reading overlaps aggregating. Files without a known size, such as
+    /// pipes or `/proc` entries, are read sequentially into memory instead.
+    Read,
+}
+
+impl Backend {
+    pub const ALL: [Backend; 2] = [Backend::Mmap, Backend::Read];
+
+    pub fn open(self, path: &Path) -> io::Result<Input> {
+        let file = File::open(path)?;
+        match self {
+            Backend::Mmap => Ok(Input::Mmap(unsafe { Mmap::map(&file)? })),
+            Backend::Read => {
+                let metadata = file.metadata()?;
+                if !metadata.is_file() || metadata.len() == 0 {
+                    return read_to_end(&file).map(Input::Buffer);
+                }
+                let len = usize::try_from(metadata.len()).map_err(|_| io::ErrorKind::OutOfMemory)?;
+                Ok(Input::File(Pread { file, len }))
+            }
+        }
+    }
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Backend::Mmap => "mmap",
+            Backend::Read => "read",
+        })
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mmap" => Ok(Backend::Mmap),
+            "read" => Ok(Backend::Read),
+            _ => Err(format!("unknown input backend `{}`", s)),
+        }
+    }
+}
+
+/// An opened input, whichever [`Backend`] produced it.
+#[derive(Debug)]
+pub enum Input {
+    Mmap(Mmap),
+    Buffer(Buffer),
+    /// A file that is read segment by segment while it is aggregated.
+    File(Pread),
+}
+
+impl Input {
+    pub fn len(&self) -> usize {
+        match self {
+            Input::File(file) => file.len,
+            _ => self.bytes().map_or(0, <[u8]>::len),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// All bytes of the input, unless it is read segment by segment.
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match self {
+            Input::Mmap(mmap) => Some(mmap),
+            Input::Buffer(buffer) => Some(buffer),
+            Input::File(_) => None,
+        }
+    }
+
+    /// Number of `\n` in `range` of the input.
+    pub fn count_lines(&self, range: Range<usize>) -> io::Result<u64> {
+        match self {
+            Input::File(file) => file.count_lines(range),
+            _ => Ok(count(&self.bytes().unwrap_or_default()[range], b'\n') as u64),
+        }
+    }
+
+    /// Fills in the line of `err`, like [`ParseError::locate`].
+    pub fn locate(&self, mut err: ParseError) -> io::Result<ParseError> {
+        err.line = 1 + self.count_lines(0..err.offset)?;
+        Ok(err)
+    }
+}
+
+/// A file of known size, read with positional reads.
+#[derive(Debug)]
+pub struct Pread {
+    file: File,
+    len: usize,
+}
+
+impl Pread {
+    /// Reads the lines that start within `claim` into `buffer`: from the first line start
+    /// at or after `claim.start` up to the first one at or after `claim.end`, like
+    /// [`line_start`](crate::partition::line_start) on the whole file. Returns the
+    /// offset of the first line in the file along with the lines, which are empty when
+    /// no line starts within the claim.
+    pub fn read_lines<'b>(&self, claim: Range<usize>, buffer: &'b mut Buffer) -> io::Result<(usize, &'b [u8])> {
+        let end = claim.end.min(self.len);
+        if claim.start >= end {
+            return Ok((end, &[]));
+        }
+        // From the byte before the claim, whose `\n` would make the claim start a line.
+        let from = claim.start.saturating_sub(1);
+        let mut filled = (end + TAIL).min(self.len) - from;
+        buffer.reserve(filled);
+        read_exact_at(&self.file, &mut buffer[..filled], from as u64)?;
+
+        let first = if claim.start == 0 {
+            0
+        } else {
+            match buffer[..end - from].iter().position(|&b| b == b'\n') {
+                Some(i) => i + 1,
+                None => return Ok((end, &[])),
+            }
+        };
+        if from + first >= end {
+            return Ok((end, &[]));
+        }
+        // The last line ends at the first `\n` from the last byte of the claim on.
+        let mut searched = end - 1 - from;
+        let stop = loop {
+            if let Some(i) = buffer[searched..filled].iter().position(|&b| b == b'\n') {
+                break searched + i + 1;
+            }
+            if from + filled == self.len {
+                break filled;
+            }
+            searched = filled;
+            let more = TAIL.min(self.len - from - filled);
+            buffer.reserve(filled + more);
+            read_exact_at(&self.file, &mut buffer[filled..filled + more], (from + filled) as u64)?;
+            filled += more;
+        };
+        Ok((from + first, &buffer[first..stop]))
+    }
+
+    fn count_lines(&self, range: Range<usize>) -> io::Result<u64> {
+        let mut buffer = Buffer::zeroed(BLOCK_SIZE);
+        let mut lines = 0;
+        let mut offset = range.start;
+        while offset < range.end {
+            let n = BLOCK_SIZE.min(range.end - offset);
+            read_exact_at(&self.file, &mut buffer[..n], offset as u64)?;
+            lines += count(&buffer[..n], b'\n') as u64;
+            offset += n;
+        }
+        Ok(lines)
+    }
+}
+
+/// Bytes per read when a file is read front to back.
+const BLOCK_SIZE: usize = 8 << 20;
+
+/// A zero-initialized, page-aligned byte buffer.
+#[derive(Debug)]
+pub struct Buffer {
+    ptr: NonNull<u8>,
+    len: usize,
+    capacity: usize,
+}
+
+// The buffer owns its allocation like a `Vec<u8>`.
+unsafe impl Send for Buffer {}
+unsafe impl Sync for Buffer {}
+
+impl Buffer {
+    pub fn zeroed(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let ptr = unsafe { alloc::alloc_zeroed(Self::layout(capacity)) };
+        Buffer { ptr: Self::check(ptr, capacity), len: capacity, capacity }
+    }
+
+    fn layout(capacity: usize) -> Layout {
+        Layout::from_size_align(capacity, ALIGN).expect("buffer too large")
+    }
+
+    fn check(ptr: *mut u8, capacity: usize) -> NonNull<u8> {
+        NonNull::new(ptr).unwrap_or_else(|| alloc::handle_alloc_error(Self::layout(capacity)))
+    }
+
+    /// Doubles the capacity, zeroing the new bytes, and makes all of it visible.
+    fn grow(&mut self) {
+        let capacity = self.capacity * 2;
+        let layout = Self::layout(self.capacity);
+        let ptr = Self::check(unsafe { alloc::realloc(self.ptr.as_ptr(), layout, capacity) }, capacity);
+        unsafe { ptr.as_ptr().add(self.capacity).write_bytes(0, capacity - self.capacity) };
+        self.ptr = ptr;
+        self.capacity = capacity;
+        self.len = capacity;
+    }
+
+    /// Grows the buffer until it holds at least `len` bytes.
+    fn reserve(&mut self, len: usize) {
+        while self.capacity < len {
+            self.grow();
+        }
+    }
+}
+
+impl Deref for Buffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl std::ops::DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), Self::layout(self.capacity)) };
+    }
+}
+
+/// Reads a stream whose size is not known up front.
+fn read_to_end(mut file: &File) -> io::Result<Buffer> {
+    let mut buffer = Buffer::zeroed(BLOCK_SIZE);
+    let mut len = 0;
+    loop {
+        if len == buffer.capacity {
+            buffer.grow();
+        }
+        match file.read(&mut buffer[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    buffer.len = len;
+    Ok(buffer)
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::partition::line_start;
+
+    #[test]
+    fn backends_read_the_same_lines() {
+        let path = std::env::temp_dir().join(format!("rs-1brc-input-{}", std::process::id()));
+        let mut bytes = Vec::new();
+        for i in 0..2000 {
+            // Mostly short lines, and a few longer than the tail.
+            let len = if i % 500 == 7 { TAIL * 3 + 5 } else { i % 90 };
+            bytes.extend((0..len).map(|j| b'a' + (j % 26) as u8));
+            bytes.push(b'\n');
+        }
+        for trailing_newline in [true, false] {
+            if !trailing_newline {
+                bytes.pop();
+            }
+            std::fs::write(&path, &bytes).unwrap();
+            assert_eq!(*Backend::Mmap.open(&path).unwrap().bytes().unwrap(), bytes[..]);
+            let Input::File(file) = Backend::Read.open(&path).unwrap() else {
+                panic!("a regular file is read in segments");
+            };
+            let mut buffer = Buffer::zeroed(1);
+            for size in [1, 90, TAIL, 100_000, bytes.len()] {
+                for start in (0..bytes.len()).step_by(size) {
+                    let (offset, lines) = file.read_lines(start..start + size, &mut buffer).unwrap();
+                    let first = line_start(&bytes, start);
+                    assert_eq!(lines, &bytes[first..line_start(&bytes, start + size)], "{} at {}", size, start);
+                    if !lines.is_empty() {
+                        assert_eq!(offset, first);
+                    }
+                }
+            }
+            let input = Input::File(file);
+            assert_eq!(input.count_lines(10..bytes.len()).unwrap(), count(&bytes[10..], b'\n') as u64);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_handles_files_without_a_size() {
+        let mut buffer = read_to_end(&File::open("Cargo.toml").unwrap()).unwrap();
+        assert_eq!(buffer.as_ptr() as usize % ALIGN, 0);
+        assert_eq!(*buffer, std::fs::read("Cargo.toml").unwrap()[..]);
+        buffer.grow();
+        assert!(buffer[BLOCK_SIZE..].iter().all(|&b| b == 0));
+
+        // `/proc` files report a size of zero and cannot be mapped meaningfully.
+        #[cfg(target_os = "linux")]
+        assert!(Backend::Read.open(Path::new("/proc/self/status")).unwrap().bytes().unwrap().ends_with(b"\n"));
+    }
+}
@@ -0,0 +1,70 @@
+use std::path::Path;
+
+pub mod aggregator;
+pub mod approach_0;
+pub mod approach_1;
+pub mod approach_10;
+pub mod approach_11;
+pub mod approach_2;
+pub mod approach_3;
+pub mod approach_4;
+pub mod approach_5;
+pub mod approach_6;
+pub mod approach_7;
+pub mod approach_8;
+pub mod approach_9;
+pub mod delim;
+pub mod error;
+pub mod files;
+pub mod filter;
+pub mod generate;
+pub mod histogram;
+pub mod input;
+pub mod merge;
+pub mod parse;
+pub mod partition;
+pub mod reduce;
+pub mod report;
+pub mod scan;
+pub mod schedule;
+pub mod sketch;
+pub mod snapshot;
+pub mod stats;
+pub mod stream;
+pub mod table;
+
+pub use aggregator::Aggregator;
+pub use error::{Error, OnError, ParseError};
+pub use files::FileError;
+pub use filter::StationFilter;
+pub use input::Backend;
+pub use report::render;
+pub use stats::{StationStats, Stations};
+
+/// The fastest aggregator this crate ships.
+pub fn best() -> &'static dyn Aggregator {
+    &approach_9::Approach
+}
+
+pub fn aggregate_file(path: &Path) -> Result<Stations, Error> {
+    best().aggregate_file(path)
+}
+
+/// Like [`aggregate_file`], for only the stations that `filter` selects; see
+/// [`approach_9::Filtered`].
+pub fn aggregate_file_filtered(path: &Path, filter: &StationFilter) -> Result<Stations, Error> {
+    approach_9::Filtered::new(filter).aggregate_file(path)
+}
+
+/// Aggregates every file named by `patterns`, which may be files, directories or globs;
+/// see [`files::expand`].
+pub fn aggregate_files<P: AsRef<Path>>(patterns: &[P]) -> Result<Stations, FileError> {
+    aggregate_files_filtered(patterns, &StationFilter::default())
+}
+
+/// Like [`aggregate_files`], for only the stations that `filter` selects.
+pub fn aggregate_files_filtered<P: AsRef<Path>>(patterns: &[P], filter: &StationFilter) -> Result<Stations, FileError> {
+    let paths = files::expand(patterns)?;
+    let options = files::Options { filter: filter.clone(), ..files::Options::default() };
+    Ok(files::aggregate(&paths, options)?.stations)
+}
@@ -1,94 +1,290 @@
-#![feature(portable_simd)]
-#![feature(core_intrinsics)]
-#![feature(iter_map_windows)]
+use std::collections::BTreeMap;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
 
-use std::hash::{BuildHasher, Hash};
-use std::ops::{BitAnd, Not, Sub};
-use std::path::Path;
-use std::simd::cmp::SimdPartialEq;
+use rs_1brc::error::Aggregate;
+use rs_1brc::files::{self, FileSummary, FilesAggregate};
+use rs_1brc::histogram::{Histogram, Histograms};
+use rs_1brc::input::Backend;
+use rs_1brc::sketch::{Sketch, Sketches};
+use rs_1brc::{aggregator, approach_11, approach_9, generate, report, snapshot};
+use rs_1brc::{Aggregator, Error, FileError, StationStats, Stations};
 
-mod approach_0;
-mod approach_1;
-mod approach_2;
-mod approach_3;
-mod approach_4;
-mod approach_5;
-mod approach_6;
-mod approach_7;
-mod approach_8;
-mod approach_9;
+mod cli;
 
-fn main() {
-    // let path = Path::new("measurements_simple.txt");
-    // let path = Path::new("measurements_1M.txt");
-    // let path = Path::new("measurements_100M.txt");
-    let path = Path::new("measurements_1G.txt");
-    // println!("approach_0: {:?}", timeit(|| approach_0::run(path), 5));
-    // println!("approach_1: {:?}", timeit(|| approach_1::run(path), 5));
-    // println!("approach_2: {:?}", timeit(|| approach_2::run(path), 5));
-    // println!("approach_3: {:?}", timeit(|| approach_3::run(path), 5));
-    // println!("approach_4: {:?}", timeit(|| approach_4::run(path), 5));
-    // println!("approach_5: {:?}", timeit(|| approach_5::run(path), 5));
-    // println!("approach_6: {:?}", timeit(|| approach_6::run(path), 5));
-    // println!("approach_7: {:?}", timeit(|| approach_7::run(path), 1));
-    // println!("approach_8: {:?}", timeit(|| approach_8::run(path), 1));
-    println!("approach_9: {:?}", timeit(|| approach_9::run(path), 1));
+fn main() -> ExitCode {
+    match cli::parse(std::env::args().skip(1)) {
+        Ok(cli::Command::Run(args)) => run(args),
+        Ok(cli::Command::Generate(args)) => generate(args),
+        Ok(cli::Command::Merge(args)) => merge(args),
+        Ok(cli::Command::Help) => print(cli::USAGE),
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, cli::USAGE);
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn init_threads(threads: Option<usize>) {
+    if let Some(threads) = threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .unwrap();
+    }
+}
 
+fn generate(args: cli::GenerateArgs) -> ExitCode {
+    init_threads(args.threads);
+    let file = match std::fs::File::create(&args.output) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("error: cannot create {}: {}", args.output.display(), err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let config = generate::Config {
+        rows: args.rows,
+        seed: args.seed,
+        stations: args.stations,
+    };
+    let (elapsed, result) = timeit(|| generate::generate(&mut BufWriter::new(&file), &config), 1);
+    if let Err(err) = result {
+        eprintln!("error: cannot write {}: {}", args.output.display(), err);
+        return ExitCode::FAILURE;
+    }
+    if !args.quiet {
+        eprintln!("generate: {} rows in {:?}", args.rows, elapsed);
+    }
+    ExitCode::SUCCESS
+}
 
-    // let pattern = u8x32::splat(0x0A);
-    // let cl_subtract = u8x32::splat(0x01);
-    // let top_bitmask = u8x32::splat(0x80);
-    // //
-    // let target = test.as_bytes();
-    // let target = u8x32::from_slice(&target[..32]);
-    // //
-    // let result = target.simd_eq(comparator);
-    // println!("Result: {:032b}", result.to_bitmask());
-    // result.first_set()
-    //     .map(|x| println!("First set: {}", x))
-    //     .unwrap_or_else(|| println!("No set bit found"));
-    //
-    // println!("{}", String::from_utf8_lossy(&test.as_bytes()[0..10]));
-    // println!("{}", String::from_utf8_lossy(&test.as_bytes()[11..22]))
-    //
-    // let a = u8x4::from_array([1, 0, 0, 0]);
-    // let b = u8x4::from_array([0, 0, 0, 1]);
-    // let c = a.sub(b);
-    // println!("{:?}", c.to_array());
+fn run(args: cli::Args) -> ExitCode {
+    let lanes;
+    let aggregator: &dyn Aggregator = match (args.approach.as_str(), args.lanes) {
+        ("11", Some(n)) => {
+            lanes = approach_11::Approach::new(n, approach_11::SEGMENT_SIZE);
+            &lanes
+        }
+        (_, Some(_)) => {
+            eprintln!("error: `--lanes` only applies to approach 11\n\n{}", cli::USAGE);
+            return ExitCode::from(2);
+        }
+        ("best", None) => rs_1brc::best(),
+        (n, None) => match aggregator::by_name(&format!("approach_{}", n)) {
+            Some(aggregator) => aggregator,
+            None => {
+                eprintln!("error: unknown approach `{}`\n\n{}", n, cli::USAGE);
+                return ExitCode::from(2);
+            }
+        },
+    };
+    if args.io == Backend::Read && !aggregator.preads() {
+        eprintln!("error: `--io read` needs an approach that reads its own segments: 3, 9 or 11\n\n{}", cli::USAGE);
+        return ExitCode::from(2);
+    }
+    init_threads(args.threads);
+
+    let paths = if args.inputs[0].as_os_str() == "-" {
+        Vec::new()
+    } else {
+        match files::expand(&args.inputs) {
+            Ok(paths) => paths,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                return ExitCode::FAILURE;
+            }
+        }
+    };
+    let mut filter = args.filter.clone();
+    for path in &args.allow_lists {
+        match rs_1brc::filter::read_names(path) {
+            Ok(names) => filter.names.extend(names),
+            Err(err) => {
+                eprintln!("error: cannot read {}: {}", path.display(), err);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let scanned = matches!(args.approach.as_str(), "best" | "9");
+    if !filter.is_empty() && !scanned {
+        eprintln!("error: station filters run inside the scan of approach 9\n\n{}", cli::USAGE);
+        return ExitCode::from(2);
+    }
+    let filtered = approach_9::Filtered::new(&filter);
+    let aggregator: &dyn Aggregator = if filter.is_empty() { aggregator } else { &filtered };
+    let options = files::Options {
+        backend: args.io,
+        on_error: args.on_error,
+        rows: args.rows,
+        filter: filter.clone(),
+    };
+    if let Some(percentiles) = &args.percentiles {
+        if paths.is_empty() {
+            eprintln!("error: histograms and sketches need input files, not stdin\n\n{}", cli::USAGE);
+            return ExitCode::from(2);
+        }
+        if !scanned {
+            eprintln!("error: histograms and sketches are always aggregated with approach 9\n\n{}", cli::USAGE);
+            return ExitCode::from(2);
+        }
+        if args.sketch {
+            let (elapsed, result) = timeit(|| files::aggregate_as::<Sketch>(&paths, options.clone()), args.repeat);
+            let render = |stations: &Sketches| report::render_sketches(stations, percentiles, args.stddev, args.output);
+            return finish(&args, elapsed, result, render, None::<fn(&Sketch) -> StationStats>);
+        }
+        let (elapsed, result) = timeit(|| files::aggregate_as::<Histogram>(&paths, options.clone()), args.repeat);
+        let render = |stations: &Histograms| report::render_percentiles(stations, percentiles, args.stddev, args.output);
+        return finish(&args, elapsed, result, render, Some(Histogram::stats));
+    }
+
+    let (elapsed, result) = match paths.as_slice() {
+        [] => {
+            let aggregate = || aggregator.aggregate_reader(&mut std::io::stdin(), args.on_error);
+            let (elapsed, result) = timeit(aggregate, 1);
+            (elapsed, summarize(PathBuf::from("<stdin>"), result, args.rows))
+        }
+        [path] => {
+            let input = match args.io.open(path) {
+                Ok(input) => input,
+                Err(err) => {
+                    eprintln!("error: cannot {} {}: {}", args.io, path.display(), err);
+                    return ExitCode::FAILURE;
+                }
+            };
+            let aggregate = || aggregator.aggregate_input(&input, args.on_error);
+            let (elapsed, result) = timeit(aggregate, args.repeat);
+            (elapsed, summarize(path.clone(), result, args.rows))
+        }
+        paths => {
+            if !scanned {
+                eprintln!("error: several inputs are always aggregated with approach 9\n\n{}", cli::USAGE);
+                return ExitCode::from(2);
+            }
+            timeit(|| files::aggregate(paths, options.clone()), args.repeat)
+        }
+    };
+    let render = |stations: &Stations| report::render_with(stations, args.stddev, args.output);
+    finish(&args, elapsed, result, render, Some(|stats: &StationStats| *stats))
+}
 
-    // let a = 0x01000000_u32;
-    // let clsub = 0x01010101_u32;
-    // let ofprot = 0x80808080_u32;
-    //
-    // println!("{:032b}", a);
-    // println!("----------------------------");
-    // println!("{:032b}", a.wrapping_sub(clsub));
-    // println!("----------------------------");
-    // println!("{:032b}", (a.not()).bitand(ofprot));
-    // println!("----------------------------");
-    // println!("{:032b}", (a.wrapping_sub(clsub)).bitand(a.not().bitand(ofprot)));
-    // println!("============================");
-    // let a = b";;;;".to_owned();
-    // let a = u32::from_be_bytes(a);
-    // let b = b":;;;".to_owned();
-    // let b = u32::from_be_bytes(b);
-    // let c = a ^ b;
-    //
-    //
-    // println!("{:032b}", a);
-    // println!("{:032b}", c);
-    // println!("----------------------------");
-    // println!("{:032b}", c.wrapping_sub(clsub));
-    // println!("----------------------------");
-    // println!("{:032b}", (!c & ofprot));
-    // println!("----------------------------");
-    // println!("{:032b}", (c.wrapping_sub(clsub)) & (!c & ofprot));
+/// Reports everything but the stations to stderr, saves a snapshot of their `stats` if
+/// asked to, and prints the stations with `render`.
+fn finish<V>(
+    args: &cli::Args,
+    elapsed: Duration,
+    result: Result<FilesAggregate<V>, FileError>,
+    render: impl Fn(&BTreeMap<String, V>) -> String,
+    stats: Option<impl Fn(&V) -> StationStats>,
+) -> ExitCode {
+    let result = match result {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    for file in &result.files {
+        for err in &file.errors {
+            eprintln!("warning: {}: {}", file.path.display(), err);
+        }
+    }
+    if args.workers {
+        if result.workers.is_empty() {
+            eprintln!("approach_{} does not schedule segments", args.approach);
+        }
+        for (i, worker) in result.workers.iter().enumerate() {
+            eprintln!(
+                "worker {}: {} segments, {} MiB, busy {:?}",
+                i,
+                worker.segments,
+                worker.bytes >> 20,
+                worker.busy
+            );
+        }
+    }
+    for file in &result.files {
+        if let Some(rows) = file.rows {
+            eprintln!("{}: {} rows", file.path.display(), rows);
+        }
+    }
+    if !args.quiet {
+        let rejected: u64 = result.files.iter().map(|file| file.rejected).sum();
+        if rejected > 0 {
+            eprintln!("skipped {} malformed lines", rejected);
+        }
+        eprintln!("approach_{}: {:?}", args.approach, elapsed);
+    }
+    if let (Some(path), Some(stats)) = (&args.save, stats) {
+        let stations = result.stations.iter().map(|(name, v)| (name.clone(), stats(v))).collect();
+        if let Err(err) = snapshot::save_file(&stations, path) {
+            eprintln!("error: cannot write {}: {}", path.display(), err);
+            return ExitCode::FAILURE;
+        }
+    }
+    print(&render(&result.stations))
+}
+
+/// Presents the result of a single input like that of several.
+fn summarize(
+    path: PathBuf,
+    result: Result<Aggregate, Error>,
+    rows: bool,
+) -> Result<FilesAggregate, FileError> {
+    let aggregate = result.map_err(|error| FileError { path: path.clone(), error })?;
+    let summary = FileSummary {
+        path,
+        rows: rows.then(|| aggregate.stations.values().map(|stats| stats.count).sum()),
+        rejected: aggregate.rejected,
+        errors: aggregate.errors,
+    };
+    Ok(FilesAggregate {
+        stations: aggregate.stations,
+        files: vec![summary],
+        workers: aggregate.workers,
+    })
+}
+
+fn merge(args: cli::MergeArgs) -> ExitCode {
+    let mut partials = Vec::with_capacity(args.snapshots.len());
+    for path in &args.snapshots {
+        match snapshot::load_file(path) {
+            Ok(stations) => partials.push(stations),
+            Err(err) => {
+                eprintln!("error: cannot read {}: {}", path.display(), err);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let stations = snapshot::merge(partials);
+    if let Some(path) = &args.save {
+        if let Err(err) = snapshot::save_file(&stations, path) {
+            eprintln!("error: cannot write {}: {}", path.display(), err);
+            return ExitCode::FAILURE;
+        }
+    }
+    print(&report::render_with(&stations, args.stddev, args.output))
+}
+
+/// Prints `output` to stdout; a reader that went away, like `head`, is not an error.
+fn print(output: &str) -> ExitCode {
+    match writeln!(io::stdout().lock(), "{}", output) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: cannot write the result: {}", err);
+            ExitCode::FAILURE
+        }
+    }
 }
 
-fn timeit<F: Fn() -> ()>(f: F, count: usize) -> std::time::Duration {
+fn timeit<T, F: Fn() -> T>(f: F, count: usize) -> (Duration, T) {
     let start = std::time::Instant::now();
-    for _ in 0..count {
-        f();
+    let mut result = f();
+    for _ in 1..count {
+        result = f();
     }
-    start.elapsed() / count as u32
+    (start.elapsed() / count as u32, result)
 }
\ No newline at end of file
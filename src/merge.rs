@@ -0,0 +1,178 @@
+//! Parallel merge of per-worker tables.
+//!
+//! Entries are split into shards by the high bits of their `hash_key`, and each shard is
+//! folded by exactly one task, so shards need no locks and the work is proportional to
+//! the number of occupied entries, not to table capacity. The high bits are used because
+//! the low ones pick the slot, and a shard drawn from them would probe in clusters.
+
+use std::borrow::Borrow;
+use std::hash::Hash;
+
+use hashbrown::HashMap;
+use rayon::prelude::*;
+
+use crate::table::{hash_key, StationTable};
+
+/// Number of shards, as a power of two: a few per thread so that uneven shards balance.
+fn shard_bits() -> u32 {
+    (rayon::current_num_threads() * 4).next_power_of_two().trailing_zeros()
+}
+
+#[inline]
+fn shard(hash: u64, bits: u32) -> usize {
+    hash.checked_shr(64 - bits).unwrap_or(0) as usize
+}
+
+/// The entries of one table, split by shard.
+type Buckets<E> = Vec<Vec<E>>;
+
+fn bucket<E: Clone>(entries: impl Iterator<Item = (u64, E)>, bits: u32) -> Buckets<E> {
+    let mut buckets = vec![Vec::new(); 1 << bits];
+    for (hash, entry) in entries {
+        buckets[shard(hash, bits)].push(entry);
+    }
+    buckets
+}
+
+/// Merges `tables` into one, combining the values of a key present in several tables
+/// with `f`. On a single thread the shards buy nothing, and the tables are folded in
+/// order instead.
+pub fn merge_tables<V, F>(tables: Vec<StationTable<V>>, f: F) -> StationTable<V>
+where
+    V: Clone + Send + Sync,
+    F: Fn(&mut V, &V) + Sync,
+{
+    if tables.len() <= 1 || rayon::current_num_threads() == 1 {
+        let mut tables = tables.into_iter();
+        let mut result = tables.next().unwrap_or_default();
+        for table in tables {
+            result.merge(table, |a, b| f(a, &b));
+        }
+        return result;
+    }
+    let bits = shard_bits();
+    // The largest table only bounds the keys of a shard from below: tables that hold
+    // different stations add up. Start every shard at four slots per key of its share of
+    // the largest table, and let it grow from there.
+    let shard_len = tables.iter().map(StationTable::len).max().unwrap_or(0) >> bits;
+    let buckets: Vec<Buckets<_>> = tables
+        .par_iter()
+        .map(|table| bucket(table.iter_hashed().map(|entry| (entry.0, entry)), bits))
+        .collect();
+    let shards: Vec<StationTable<V>> = (0..1 << bits)
+        .into_par_iter()
+        .map(|s| {
+            let mut shard = StationTable::with_capacity(shard_len * 4);
+            for &(hash, key, value) in buckets.iter().flat_map(|buckets| &buckets[s]) {
+                let mut inserted = false;
+                let slot = shard.get_or_insert_with(key, hash, || {
+                    inserted = true;
+                    value.clone()
+                });
+                if !inserted {
+                    f(slot, value);
+                }
+            }
+            shard
+        })
+        .collect();
+
+    // Shards hold disjoint keys, so this only inserts.
+    let len = shards.iter().map(StationTable::len).sum::<usize>();
+    let mut result = StationTable::with_capacity(len * 2);
+    for shard in shards {
+        result.merge(shard, |_, _| unreachable!("a key landed in two shards"));
+    }
+    result
+}
+
+/// Like [`merge_tables`] for maps keyed by station name, borrowed or owned.
+pub fn merge_maps<K, V, F>(maps: Vec<HashMap<K, V>>, f: F) -> HashMap<K, V>
+where
+    K: Borrow<str> + Hash + Eq + Clone + Send + Sync,
+    V: Send + Sync + Clone,
+    F: Fn(&mut V, &V) + Sync,
+{
+    if maps.len() <= 1 || rayon::current_num_threads() == 1 {
+        let mut maps = maps.into_iter();
+        let mut result = maps.next().unwrap_or_default();
+        for (key, value) in maps.flatten() {
+            result.entry(key).and_modify(|e| f(e, &value)).or_insert(value);
+        }
+        return result;
+    }
+    let bits = shard_bits();
+    let buckets: Vec<Buckets<_>> = maps
+        .par_iter()
+        .map(|map| bucket(map.iter().map(|entry| (hash_key(Borrow::<str>::borrow(entry.0).as_bytes()), entry)), bits))
+        .collect();
+    let shards: Vec<HashMap<K, V>> = (0..1 << bits)
+        .into_par_iter()
+        .map(|s| {
+            let mut shard = HashMap::new();
+            for &(key, value) in buckets.iter().flat_map(|buckets| &buckets[s]) {
+                shard.entry(key.clone())
+                    .and_modify(|e| f(e, value))
+                    .or_insert_with(|| value.clone());
+            }
+            shard
+        })
+        .collect();
+    let mut result = HashMap::with_capacity(shards.iter().map(HashMap::len).sum());
+    result.extend(shards.into_iter().flatten());
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+
+    fn sorted(entries: impl IntoIterator<Item = (String, u64)>) -> Vec<(String, u64)> {
+        let mut entries: Vec<_> = entries.into_iter().collect();
+        entries.sort();
+        entries
+    }
+
+    fn names(worker: usize) -> impl Iterator<Item = (String, u64)> {
+        // Overlapping key ranges, 10k distinct names in total.
+        (worker * 1500..worker * 1500 + 4000)
+            .map(move |i| (format!("Station {}", i), (i * worker) as u64))
+    }
+
+    #[test]
+    fn sharded_merges_match_a_sequential_fold() {
+        for threads in [1, 4] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let mut expected = HashMap::<String, u64>::new();
+            let mut tables = Vec::new();
+            let mut maps = Vec::new();
+            for worker in 0..5 {
+                let mut table = StationTable::with_capacity(16);
+                let mut map = HashMap::new();
+                for (name, value) in names(worker) {
+                    *expected.entry(name.clone()).or_default() += value;
+                    let hash = hash_key(name.as_bytes());
+                    *table.get_or_insert_with(name.as_bytes(), hash, || 0) += value;
+                    map.insert(Cow::Owned(name), value);
+                }
+                tables.push(table);
+                maps.push(map);
+            }
+            let expected = sorted(expected);
+
+            let table = pool.install(|| merge_tables(tables.clone(), |a, b| *a += b));
+            let table = table
+                .iter()
+                .map(|(key, &value)| (String::from_utf8_lossy(key).into_owned(), value));
+            assert_eq!(sorted(table), expected, "{} threads", threads);
+            let map = pool.install(|| merge_maps(maps.clone(), |a, b| *a += b));
+            let map = map.into_iter().map(|(key, value)| (key.into_owned(), value));
+            assert_eq!(sorted(map), expected, "{} threads", threads);
+        }
+    }
+}
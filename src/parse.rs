@@ -0,0 +1,176 @@
+use crate::error::ParseErrorKind;
+
+/// Longest station name allowed by the 1BRC rules, in bytes.
+pub const MAX_NAME_LEN: usize = 100;
+
+/// Validates a record split at its first `;` and returns its temperature.
+///
+/// `value` is `None` when the line has no separator.
+#[inline]
+pub fn parse_record(name: &[u8], value: Option<&[u8]>) -> Result<i16, ParseErrorKind> {
+    parse_value(name, value)
+}
+
+/// Like [`parse_record`], for any kind of [`Reading`].
+#[inline]
+pub fn parse_value<T: Reading>(name: &[u8], value: Option<&[u8]>) -> Result<T, ParseErrorKind> {
+    let value = value.ok_or(ParseErrorKind::MissingSeparator)?;
+    if name.len() > MAX_NAME_LEN {
+        return Err(ParseErrorKind::NameTooLong);
+    }
+    T::parse(value).ok_or(T::INVALID)
+}
+
+/// A value read from the text after the `;` of a row.
+pub trait Reading: Copy + Send + Sync {
+    /// What a value that [`parse`](Reading::parse) rejects is reported as.
+    const INVALID: ParseErrorKind;
+
+    /// Returns `None` for anything outside the grammar of this kind of value.
+    fn parse(bytes: &[u8]) -> Option<Self>;
+}
+
+/// A 1BRC temperature in tenths of a degree.
+impl Reading for i16 {
+    const INVALID: ParseErrorKind = ParseErrorKind::BadNumber;
+
+    #[inline]
+    fn parse(bytes: &[u8]) -> Option<i16> {
+        parse_temperature(bytes)
+    }
+}
+
+/// Any decimal; see [`parse_decimal`].
+impl Reading for f64 {
+    const INVALID: ParseErrorKind = ParseErrorKind::BadDecimal;
+
+    #[inline]
+    fn parse(bytes: &[u8]) -> Option<f64> {
+        parse_decimal(bytes)
+    }
+}
+
+#[inline]
+pub fn station_name(name: &[u8]) -> Result<&str, ParseErrorKind> {
+    std::str::from_utf8(name).map_err(|_| ParseErrorKind::InvalidUtf8)
+}
+
+/// Parses a 1BRC temperature (`-?\d{1,2}\.\d`) into tenths of a degree.
+///
+/// Returns `None` for anything outside that grammar.
+#[inline]
+pub fn parse_temperature(bytes: &[u8]) -> Option<i16> {
+    let (sign, digits) = match bytes {
+        [b'-', rest @ ..] => (-1, rest),
+        _ => (1, bytes),
+    };
+    let value = match *digits {
+        [a, b'.', b] => digit(a)? * 10 + digit(b)?,
+        [a, b, b'.', c] => digit(a)? * 100 + digit(b)? * 10 + digit(c)?,
+        _ => return None,
+    };
+    Some(sign * value)
+}
+
+/// Parses a decimal (`-?\d+(\.\d+)?`) of any precision into the nearest `f64`.
+///
+/// Returns `None` for anything outside that grammar, including exponents, `inf` and
+/// `NaN`, and for values too large for a finite `f64`.
+pub fn parse_decimal(bytes: &[u8]) -> Option<f64> {
+    let (negative, digits) = match bytes {
+        [b'-', rest @ ..] => (true, rest),
+        _ => (false, bytes),
+    };
+    let mut mantissa = 0u64;
+    let mut exact = true;
+    let mut point = None;
+    for (i, &b) in digits.iter().enumerate() {
+        match b {
+            b'0'..=b'9' => match mantissa.checked_mul(10).and_then(|m| m.checked_add((b - b'0') as u64)) {
+                Some(m) => mantissa = m,
+                None => exact = false,
+            },
+            b'.' if point.is_none() && i > 0 => point = Some(i),
+            _ => return None,
+        }
+    }
+    let scale = point.map_or(0, |point| digits.len() - point - 1);
+    if digits.is_empty() || (point.is_some() && scale == 0) {
+        return None;
+    }
+    // Both the mantissa and the power of ten are exact in an `f64`, so one division
+    // rounds correctly; anything longer goes through the standard parser.
+    if exact && mantissa <= 1 << 53 && scale < POW10.len() {
+        let value = mantissa as f64 / POW10[scale];
+        return Some(if negative { -value } else { value });
+    }
+    std::str::from_utf8(bytes).ok()?.parse().ok().filter(|value: &f64| value.is_finite())
+}
+
+/// The powers of ten that an `f64` holds exactly.
+const POW10: [f64; 23] = [
+    1e0, 1e1, 1e2, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10, 1e11, 1e12, 1e13, 1e14, 1e15, 1e16, 1e17, 1e18, 1e19,
+    1e20, 1e21, 1e22,
+];
+
+#[inline(always)]
+fn digit(byte: u8) -> Option<i16> {
+    let value = byte.wrapping_sub(b'0');
+    (value < 10).then_some(value as i16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_valid_temperature() {
+        for tenths in -999..=999i16 {
+            let text = format!("{}{}.{}", if tenths < 0 { "-" } else { "" }, tenths.abs() / 10, tenths.abs() % 10);
+            assert_eq!(parse_temperature(text.as_bytes()), Some(tenths), "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_other_grammar() {
+        for text in ["", "-", "1", "12", "1.", ".1", "123.4", "1.23", "+1.0", "--1.0", "1,0", "a.0", "1.a"] {
+            assert_eq!(parse_temperature(text.as_bytes()), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn parses_decimals_of_any_precision() {
+        for (text, value) in [("0", 0.0), ("-0.0", -0.0), ("12.3", 12.3), ("123.45", 123.45), ("-250.125", -250.125), ("1000000", 1e6)] {
+            assert_eq!(parse_decimal(text.as_bytes()), Some(value), "{}", text);
+        }
+        for text in ["", "-", "1.", ".1", "-.1", "+1.0", "--1.0", "1,0", "1.2.3", "1e3", "inf", "NaN", "1.a"] {
+            assert_eq!(parse_decimal(text.as_bytes()), None, "{}", text);
+        }
+        assert_eq!(parse_value::<f64>(b"Abha", Some(b"-250.125")), Ok(-250.125));
+        assert_eq!(parse_value::<f64>(b"Abha", Some(b"25O")), Err(ParseErrorKind::BadDecimal));
+        assert_eq!(parse_value::<i16>(b"Abha", Some(b"-250.125")), Err(ParseErrorKind::BadNumber));
+    }
+
+    #[test]
+    fn rounds_long_decimals_like_the_standard_parser() {
+        let long = ["0.1000000000000000055511151231257827", "123456789012345678901234567890", "9007199254740993"];
+        let short = ["0.1", "-2.5", "9007199254740992", "0.0000000000000000000001"];
+        for text in long.into_iter().chain(["-0.30000000000000004441", "1.00000000000000000000001"]).chain(short) {
+            assert_eq!(parse_decimal(text.as_bytes()), Some(text.parse::<f64>().unwrap()), "{}", text);
+        }
+        let huge = "9".repeat(400);
+        assert_eq!(parse_decimal(huge.as_bytes()), None);
+        assert_eq!(parse_decimal(format!("-{}.5", huge).as_bytes()), None);
+    }
+
+    #[test]
+    fn classifies_bad_records() {
+        let long_name = [b'a'; MAX_NAME_LEN + 1];
+        assert_eq!(parse_record(b"Abha", Some(b"-1.5")), Ok(-15));
+        assert_eq!(parse_record(&long_name[..MAX_NAME_LEN], Some(b"1.5")), Ok(15));
+        assert_eq!(parse_record(b"Abha", None), Err(ParseErrorKind::MissingSeparator));
+        assert_eq!(parse_record(b"Abha", Some(b"1.5x")), Err(ParseErrorKind::BadNumber));
+        assert_eq!(parse_record(&long_name, Some(b"1.5")), Err(ParseErrorKind::NameTooLong));
+        assert_eq!(station_name(b"Abh\xff"), Err(ParseErrorKind::InvalidUtf8));
+    }
+}
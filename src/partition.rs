@@ -0,0 +1,119 @@
+use std::ops::Range;
+
+/// Returns the offset of the first line that starts at or after `position`.
+#[inline]
+pub fn line_start(input: &[u8], position: usize) -> usize {
+    if position == 0 || position >= input.len() {
+        return position.min(input.len());
+    }
+    input[position - 1..]
+        .iter()
+        .position(|&x| x == b'\n')
+        .map(|x| position + x)
+        .unwrap_or(input.len())
+}
+
+/// Splits `input` into at most `parts` non-empty ranges of whole lines.
+///
+/// The ranges are sorted, do not overlap and together cover `0..input.len()` exactly,
+/// so every line belongs to exactly one range. Fewer ranges are returned when the
+/// input has fewer lines than `parts`.
+pub fn partition(input: &[u8], parts: usize) -> Vec<Range<usize>> {
+    let parts = parts.max(1);
+    let mut ranges = Vec::with_capacity(parts);
+    let mut start = 0;
+    for i in 1..parts {
+        let end = line_start(input, (input.len() * i / parts).max(start));
+        if end > start {
+            ranges.push(start..end);
+            start = end;
+        }
+    }
+    if start < input.len() {
+        ranges.push(start..input.len());
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregator::Aggregator;
+
+    fn assert_cover(input: &[u8], parts: usize) {
+        let ranges = partition(input, parts);
+        assert!(ranges.len() <= parts.max(1));
+        let mut expected = 0;
+        for range in &ranges {
+            assert_eq!(range.start, expected, "gap or overlap at {:?}", range);
+            assert!(range.end > range.start, "empty range {:?}", range);
+            assert!(range.start == 0 || input[range.start - 1] == b'\n');
+            assert!(range.end == input.len() || input[range.end - 1] == b'\n');
+            expected = range.end;
+        }
+        assert_eq!(expected, input.len());
+    }
+
+    fn lines(count: usize, trailing_newline: bool) -> Vec<u8> {
+        let mut input = Vec::new();
+        for i in 0..count {
+            input.extend_from_slice(format!("Station{};{}.{}\n", i % 7, i % 40, i % 10).as_bytes());
+        }
+        if !trailing_newline {
+            input.pop();
+        }
+        input
+    }
+
+    #[test]
+    fn empty_input_has_no_ranges() {
+        assert!(partition(b"", 4).is_empty());
+    }
+
+    #[test]
+    fn ranges_cover_input_exactly() {
+        for count in [1, 2, 3, 10, 97, 1000] {
+            for trailing_newline in [true, false] {
+                let input = lines(count, trailing_newline);
+                for parts in [0, 1, 2, 3, 7, 16, 64, 2000] {
+                    assert_cover(&input, parts);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn single_range_starts_at_zero() {
+        let input = lines(10, true);
+        assert_eq!(partition(&input, 1), vec![0..input.len()]);
+    }
+
+    #[test]
+    fn more_parts_than_lines() {
+        let input = lines(3, false);
+        let ranges = partition(&input, 64);
+        assert_eq!(ranges.len(), 3);
+        assert_cover(&input, 64);
+    }
+
+    #[test]
+    fn every_line_is_aggregated_once() {
+        for count in [1, 2, 5, 1000] {
+            for trailing_newline in [true, false] {
+                let input = lines(count, trailing_newline);
+                for threads in [1, 2, 3, 8, 32] {
+                    let pool = rayon::ThreadPoolBuilder::new()
+                        .num_threads(threads)
+                        .build()
+                        .unwrap();
+                    let aggregators: [&dyn Aggregator; 2] = [&crate::approach_3::Approach, &crate::approach_9::Approach];
+                    for aggregator in aggregators {
+                        let stations = pool.install(|| aggregator.aggregate(&input)).unwrap();
+                        let rows: u64 = stations.values().map(|s| s.count).sum();
+                        assert_eq!(rows, count as u64, "{} lines on {} threads", count, threads);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Custom per-station statistics.
+//!
+//! Any [`Reducer`] can take the place of [`StationStats`](crate::StationStats) in the
+//! scan of approach 9, which is compiled separately for each one. This module has the
+//! entry point and a few reducers that are not worth an approach of their own.
+
+use std::collections::BTreeMap;
+
+use crate::approach_9;
+use crate::error::{Aggregate, ErrorSink, OnError, ParseError};
+use crate::filter::{Selection, StationFilter};
+use crate::schedule::{self, SEGMENT_SIZE};
+use crate::table::StationTable;
+
+pub use crate::stats::{Position, Reducer};
+
+/// Reduces every station of `input` with `R`, with the scanner and scheduling of
+/// approach 9.
+pub fn aggregate<R: Reducer>(
+    input: &[u8],
+    on_error: OnError,
+) -> Result<Aggregate<BTreeMap<String, R::Output>>, ParseError> {
+    aggregate_filtered::<R>(input, &StationFilter::default(), on_error)
+}
+
+/// Like [`aggregate`], for only the stations that `filter` selects. Rows of the other
+/// stations are skipped before their value is parsed, so a malformed value there goes
+/// unnoticed.
+pub fn aggregate_filtered<R: Reducer>(
+    input: &[u8],
+    filter: &StationFilter,
+    on_error: OnError,
+) -> Result<Aggregate<BTreeMap<String, R::Output>>, ParseError> {
+    let mut errors = ErrorSink::new(on_error, input);
+    let init = || (approach_9::storage::<R>(), Selection::new(filter));
+    let step = |segment, (table, selection): &mut (StationTable<R>, Selection), errors: &mut _| {
+        approach_9::step(0, segment, table, selection, errors)
+    };
+    let merge = |scans: Vec<_>| {
+        let tables = scans.into_iter().map(|(table, _)| table).collect();
+        (StationTable::merge_all(tables), Selection::new(filter))
+    };
+    match schedule::run(input, SEGMENT_SIZE, &mut errors, init, step, merge) {
+        Ok((table, _)) => Ok(errors.finish(input, table.to_map())),
+        Err(err) => Err(err.locate(input)),
+    }
+}
+
+/// The first temperature of a station in input order, in tenths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct First(Option<(Position, i16)>);
+
+impl Reducer for First {
+    type Value = i16;
+    type Output = Option<i16>;
+
+    fn init() -> Self {
+        First(None)
+    }
+
+    #[inline]
+    fn update(&mut self, value: i16) {
+        self.update_at(value, Position::END);
+    }
+
+    #[inline]
+    fn update_at(&mut self, value: i16, at: Position) {
+        if self.0.is_none_or(|(first, _)| at < first) {
+            self.0 = Some((at, value));
+        }
+    }
+
+    fn merge(&mut self, other: &Self) {
+        if let Some((at, value)) = other.0 {
+            self.update_at(value, at);
+        }
+    }
+
+    fn finish(&self) -> Option<i16> {
+        self.0.map(|(_, value)| value)
+    }
+}
+
+/// The last temperature of a station in input order, in tenths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Last(Option<(Position, i16)>);
+
+impl Reducer for Last {
+    type Value = i16;
+    type Output = Option<i16>;
+
+    fn init() -> Self {
+        Last(None)
+    }
+
+    #[inline]
+    fn update(&mut self, value: i16) {
+        self.update_at(value, Position::END);
+    }
+
+    #[inline]
+    fn update_at(&mut self, value: i16, at: Position) {
+        if self.0.is_none_or(|(last, _)| at >= last) {
+            self.0 = Some((at, value));
+        }
+    }
+
+    fn merge(&mut self, other: &Self) {
+        if let Some((at, value)) = other.0 {
+            self.update_at(value, at);
+        }
+    }
+
+    fn finish(&self) -> Option<i16> {
+        self.0.map(|(_, value)| value)
+    }
+}
+
+/// How many temperatures of a station are above `THRESHOLD` tenths of a degree, e.g.
+/// `CountAbove<300>` for the days above 30.0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CountAbove<const THRESHOLD: i16>(u64);
+
+impl<const THRESHOLD: i16> Reducer for CountAbove<THRESHOLD> {
+    type Value = i16;
+    type Output = u64;
+
+    fn init() -> Self {
+        CountAbove(0)
+    }
+
+    #[inline]
+    fn update(&mut self, value: i16) {
+        self.0 += (value > THRESHOLD) as u64;
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.0 += other.0;
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,289 @@
+use std::fmt::{self, Display, Write};
+use std::str::FromStr;
+
+use crate::histogram::Histograms;
+use crate::sketch::{DecimalStats, Sketches};
+use crate::stats::{StationStats, Stations};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// The single `{Abha=-23.0/18.0/59.2, ...}` line of the 1BRC reference.
+    #[default]
+    Text,
+    /// One `name=min/mean/max` line per station.
+    Lines,
+    Csv,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "lines" => Ok(Format::Lines),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown output format `{}`", s)),
+        }
+    }
+}
+
+/// Formats a value kept in tenths of a degree with exactly one decimal.
+struct Tenths(i64);
+
+impl Display for Tenths {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        write!(f, "{}{}.{}", sign, abs / 10, abs % 10)
+    }
+}
+
+/// Formats a reading of any precision as its shortest exact decimal, with at least one
+/// decimal so that whole readings look like temperatures.
+struct Decimal(f64);
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.fract() == 0.0 && self.0.is_finite() {
+            write!(f, "{:.1}", self.0)
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+/// The columns every row starts with: min, mean, max, count and standard deviation.
+trait Summary {
+    fn min(&self) -> impl Display;
+    fn mean(&self) -> impl Display;
+    fn max(&self) -> impl Display;
+    fn count(&self) -> u64;
+    fn stddev(&self) -> f64;
+}
+
+impl Summary for StationStats {
+    fn min(&self) -> impl Display {
+        Tenths(self.min as i64)
+    }
+
+    fn mean(&self) -> impl Display {
+        Tenths(self.mean_tenths())
+    }
+
+    fn max(&self) -> impl Display {
+        Tenths(self.max as i64)
+    }
+
+    fn count(&self) -> u64 {
+        self.count
+    }
+
+    fn stddev(&self) -> f64 {
+        StationStats::stddev(self)
+    }
+}
+
+impl Summary for DecimalStats {
+    fn min(&self) -> impl Display {
+        Decimal(self.min)
+    }
+
+    fn mean(&self) -> impl Display {
+        Decimal(DecimalStats::mean(self))
+    }
+
+    fn max(&self) -> impl Display {
+        Decimal(self.max)
+    }
+
+    fn count(&self) -> u64 {
+        self.count
+    }
+
+    fn stddev(&self) -> f64 {
+        DecimalStats::stddev(self)
+    }
+}
+
+pub fn render(stations: &Stations) -> String {
+    render_as(stations, Format::Text)
+}
+
+pub fn render_as(stations: &Stations, format: Format) -> String {
+    render_with(stations, false, format)
+}
+
+/// Like [`render_as`]; `stddev` adds the standard deviation of every station to the
+/// text and lines formats, which otherwise stay byte for byte those of the 1BRC
+/// reference. CSV and JSON always carry it.
+pub fn render_with(stations: &Stations, stddev: bool, format: Format) -> String {
+    let rows = stations.iter().map(|(name, stats)| (name.as_str(), *stats, Vec::new()));
+    render_rows(rows, stations.len(), stddev, &[], format)
+}
+
+/// Like [`render_with`], followed by the given percentiles of every station, labelled
+/// `p50`, `p99.9` and so on.
+pub fn render_percentiles(
+    stations: &Histograms,
+    percentiles: &[f64],
+    stddev: bool,
+    format: Format,
+) -> String {
+    let labels: Vec<String> = percentiles.iter().map(|p| format!("p{}", p)).collect();
+    let rows = stations.iter().map(|(name, histogram)| {
+        let values = percentiles
+            .iter()
+            .map(|&p| Tenths(histogram.percentile(p).map_or(0, i64::from)).to_string())
+            .collect();
+        (name.as_str(), histogram.stats(), values)
+    });
+    render_rows(rows, stations.len(), stddev, &labels, format)
+}
+
+/// Like [`render_percentiles`], with the percentiles estimated by the sketches and
+/// followed by the estimated number of `distinct` readings.
+pub fn render_sketches(stations: &Sketches, percentiles: &[f64], stddev: bool, format: Format) -> String {
+    let mut labels: Vec<String> = percentiles.iter().map(|p| format!("p{}", p)).collect();
+    labels.push("distinct".to_string());
+    let rows = stations.iter().map(|(name, sketch)| {
+        let mut values: Vec<String> = percentiles
+            .iter()
+            .map(|&p| Decimal(sketch.quantiles.percentile(p).unwrap_or(0.0)).to_string())
+            .collect();
+        values.push(sketch.distinct.estimate().to_string());
+        (name.as_str(), sketch.stats, values)
+    });
+    render_rows(rows, stations.len(), stddev, &labels, format)
+}
+
+fn render_rows<'a, S, I>(rows: I, len: usize, stddev: bool, labels: &[String], format: Format) -> String
+where
+    S: Summary,
+    I: Iterator<Item = (&'a str, S, Vec<String>)>,
+{
+    let mut result = String::with_capacity(len * (32 + labels.len() * 12));
+    match format {
+        Format::Text => {
+            result.push('{');
+            for (i, (name, station, values)) in rows.enumerate() {
+                if i > 0 {
+                    result.push_str(", ");
+                }
+                write!(
+                    result,
+                    "{}={}/{}/{}",
+                    name,
+                    station.min(),
+                    station.mean(),
+                    station.max()
+                )
+                .unwrap();
+                if stddev {
+                    write!(result, " stddev={:.1}", station.stddev()).unwrap();
+                }
+                for (label, value) in labels.iter().zip(values) {
+                    write!(result, " {}={}", label, value).unwrap();
+                }
+            }
+            result.push('}');
+        }
+        Format::Lines => {
+            for (name, station, values) in rows {
+                write!(
+                    result,
+                    "{}={}/{}/{}",
+                    name,
+                    station.min(),
+                    station.mean(),
+                    station.max()
+                )
+                .unwrap();
+                if stddev {
+                    write!(result, " stddev={:.1}", station.stddev()).unwrap();
+                }
+                for (label, value) in labels.iter().zip(values) {
+                    write!(result, " {}={}", label, value).unwrap();
+                }
+                result.push('\n');
+            }
+            result.pop();
+        }
+        Format::Csv => {
+            result.push_str("station,min,mean,max,count,stddev");
+            for label in labels {
+                write!(result, ",{}", label).unwrap();
+            }
+            for (name, station, values) in rows {
+                result.push('\n');
+                csv_field(&mut result, name);
+                write!(
+                    result,
+                    ",{},{},{},{},{:.1}",
+                    station.min(),
+                    station.mean(),
+                    station.max(),
+                    station.count(),
+                    station.stddev()
+                )
+                .unwrap();
+                for value in values {
+                    write!(result, ",{}", value).unwrap();
+                }
+            }
+        }
+        Format::Json => {
+            result.push('{');
+            for (i, (name, station, values)) in rows.enumerate() {
+                if i > 0 {
+                    result.push(',');
+                }
+                json_string(&mut result, name);
+                write!(
+                    result,
+                    ":{{\"min\":{},\"mean\":{},\"max\":{},\"count\":{},\"stddev\":{:.1}",
+                    station.min(),
+                    station.mean(),
+                    station.max(),
+                    station.count(),
+                    station.stddev()
+                )
+                .unwrap();
+                for (label, value) in labels.iter().zip(values) {
+                    write!(result, ",\"{}\":{}", label, value).unwrap();
+                }
+                result.push('}');
+            }
+            result.push('}');
+        }
+    }
+    result
+}
+
+fn csv_field(out: &mut String, value: &str) {
+    if value.contains([',', '"', '\n', '\r']) {
+        out.push('"');
+        out.push_str(&value.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(value);
+    }
+}
+
+fn json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
@@ -0,0 +1,30 @@
+/// Splits the line starting at `start` at its first `;`, one byte at a time.
+///
+/// Returns the name, the value (`None` without a separator) and the offset of the next
+/// line (`None` for a final line without `\n`). This is the reference the windowed
+/// scanners are tested against, and their fallback for lines that do not fit a window.
+pub fn split_line(input: &[u8], start: usize) -> (&[u8], Option<&[u8]>, Option<usize>) {
+    let rest = &input[start..];
+    let (line, next) = match rest.iter().position(|&b| b == b'\n') {
+        Some(l) => (&rest[..l], Some(start + l + 1)),
+        None => (rest, None),
+    };
+    match line.iter().position(|&b| b == b';') {
+        Some(s) => (&line[..s], Some(&line[s + 1..]), next),
+        None => (line, None, next),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_lines() {
+        let input = b"Abha;1.0\nBroken\nCairo;2.0;3.0\nDakar;4.0";
+        assert_eq!(split_line(input, 0), (&b"Abha"[..], Some(&b"1.0"[..]), Some(9)));
+        assert_eq!(split_line(input, 9), (&b"Broken"[..], None, Some(16)));
+        assert_eq!(split_line(input, 16), (&b"Cairo"[..], Some(&b"2.0;3.0"[..]), Some(30)));
+        assert_eq!(split_line(input, 30), (&b"Dakar"[..], Some(&b"4.0"[..]), None));
+    }
+}
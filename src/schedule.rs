@@ -0,0 +1,410 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use crate::error::{Aggregate, Error, ErrorSink, OnError, ParseError};
+use crate::input::{Buffer, Input, TAIL};
+use crate::partition::line_start;
+
+pub const SEGMENT_SIZE: usize = 1 << 21;
+
+/// What one worker of a scheduled run did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WorkerStats {
+    pub segments: usize,
+    pub bytes: usize,
+    /// Time spent inside segments, excluding the final merge.
+    pub busy: Duration,
+}
+
+/// Hands out byte ranges of one or more inputs from a shared atomic cursor, in input
+/// order, without looking at their bytes.
+///
+/// Ranges never straddle two inputs; the last one of an input is simply shorter, and may
+/// run past its end.
+#[derive(Debug)]
+pub struct Claims {
+    /// Claims before each input, plus the total at the end.
+    claims: Vec<usize>,
+    segment_size: usize,
+    cursor: AtomicUsize,
+    stopped: AtomicBool,
+}
+
+impl Claims {
+    /// Claims over inputs of the given lengths. Panics if `segment_size` is zero.
+    pub fn new<I: IntoIterator<Item = usize>>(lens: I, segment_size: usize) -> Self {
+        assert!(segment_size > 0, "segment_size must be positive");
+        let mut claims = vec![0];
+        for len in lens {
+            claims.push(claims[claims.len() - 1] + len.div_ceil(segment_size));
+        }
+        Claims {
+            claims,
+            segment_size,
+            cursor: AtomicUsize::new(0),
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    /// Claims the next range, along with the index of its input.
+    pub fn next(&self) -> Option<(usize, Range<usize>)> {
+        if self.stopped.load(Ordering::Relaxed) {
+            return None;
+        }
+        let claim = self.cursor.fetch_add(1, Ordering::Relaxed);
+        if claim >= self.claims[self.claims.len() - 1] {
+            return None;
+        }
+        let index = self.claims.partition_point(|&c| c <= claim) - 1;
+        let start = (claim - self.claims[index]) * self.segment_size;
+        Some((index, start..start.saturating_add(self.segment_size)))
+    }
+
+    /// Hands out no more ranges. Ranges are claimed in input order, so after a failure
+    /// every range left lies behind the error.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Hands out newline-aligned segments of one or more inputs from a shared atomic cursor.
+///
+/// A claimed byte range owns the lines that start inside it, so segments are whole lines
+/// and cover every input exactly once without any coordination between workers. Claims
+/// never straddle two inputs; the last one of an input is simply shorter.
+#[derive(Debug)]
+pub struct Scheduler<'a> {
+    inputs: Vec<&'a [u8]>,
+    claims: Claims,
+}
+
+impl<'a> Scheduler<'a> {
+    /// Panics if `segment_size` is zero.
+    pub fn new(input: &'a [u8], segment_size: usize) -> Self {
+        Self::many(vec![input], segment_size)
+    }
+
+    /// Schedules `inputs` one after the other. Panics if `segment_size` is zero.
+    pub fn many(inputs: Vec<&'a [u8]>, segment_size: usize) -> Self {
+        let claims = Claims::new(inputs.iter().map(|input| input.len()), segment_size);
+        Scheduler { inputs, claims }
+    }
+
+    /// Claims the next non-empty segment, in input order across all callers.
+    pub fn next(&self) -> Option<&'a [u8]> {
+        self.next_in().map(|(_, segment)| segment)
+    }
+
+    /// Like [`next`](Self::next), along with the index of the input the segment is from.
+    pub fn next_in(&self) -> Option<(usize, &'a [u8])> {
+        while let Some((index, claim)) = self.claims.next() {
+            let segment = lines(self.inputs[index], claim);
+            if !segment.is_empty() {
+                return Some((index, segment));
+            }
+        }
+        None
+    }
+
+    /// Hands out no more segments; see [`Claims::stop`].
+    pub fn stop(&self) {
+        self.claims.stop();
+    }
+}
+
+/// The lines of `input` that start within `claim`.
+fn lines(input: &[u8], claim: Range<usize>) -> &[u8] {
+    &input[line_start(input, claim.start)..line_start(input, claim.end)]
+}
+
+/// Runs one worker per rayon thread over the segments of `input` and hands their tables
+/// to `merge` at the end. Per-worker telemetry is recorded in `errors`.
+pub fn run<'a, T, I, W, M>(
+    input: &'a [u8],
+    segment_size: usize,
+    errors: &mut ErrorSink,
+    init: I,
+    work: W,
+    merge: M,
+) -> Result<T, ParseError>
+where
+    T: Send,
+    I: Fn() -> T + Sync,
+    W: Fn(&'a [u8], &mut T, &mut ErrorSink) -> Result<(), ParseError> + Sync,
+    M: FnOnce(Vec<T>) -> T,
+{
+    let sinks = std::slice::from_mut(errors);
+    let work = |_, segment, result: &mut T, errors: &mut ErrorSink| work(segment, result, errors);
+    let (result, workers) = run_many(vec![input], segment_size, sinks, init, work, merge)
+        .map_err(|(_, err)| err)?;
+    for stats in workers {
+        errors.record(stats);
+    }
+    Ok(result)
+}
+
+/// Like [`run`] over several inputs at once, with one sink per input. `work` is told
+/// which input a segment is from.
+///
+/// Fails with the first error of the earliest failing input, along with its index, and
+/// returns the telemetry of every worker otherwise.
+pub fn run_many<'a, T, I, W, M>(
+    inputs: Vec<&'a [u8]>,
+    segment_size: usize,
+    sinks: &mut [ErrorSink],
+    init: I,
+    work: W,
+    merge: M,
+) -> Result<(T, Vec<WorkerStats>), (usize, ParseError)>
+where
+    T: Send,
+    I: Fn() -> T + Sync,
+    W: Fn(usize, &'a [u8], &mut T, &mut ErrorSink) -> Result<(), ParseError> + Sync,
+    M: FnOnce(Vec<T>) -> T,
+{
+    assert_eq!(inputs.len(), sinks.len(), "one sink per input");
+    let scheduler = Scheduler::many(inputs, segment_size);
+    let parents = &*sinks;
+    let workers: Vec<_> = (0..rayon::current_num_threads())
+        .into_par_iter()
+        .map(|_| {
+            let mut errors: Vec<_> = parents.iter().map(ErrorSink::fork).collect();
+            let mut result = init();
+            let mut stats = WorkerStats::default();
+            while let Some((index, segment)) = scheduler.next_in() {
+                let start = Instant::now();
+                if let Err(err) = work(index, segment, &mut result, &mut errors[index]) {
+                    scheduler.stop();
+                    return Err((index, err));
+                }
+                stats.segments += 1;
+                stats.bytes += segment.len();
+                stats.busy += start.elapsed();
+            }
+            Ok((result, errors, stats))
+        })
+        .collect();
+
+    let failed = workers.iter().filter_map(|worker| worker.as_ref().err());
+    if let Some(&(index, err)) = failed.min_by_key(|(index, err)| (*index, err.offset)) {
+        return Err((index, err));
+    }
+    Ok(collect(workers.into_iter().flatten(), sinks, merge))
+}
+
+/// Like [`run`] over an opened input, which is read with [`run_inputs`], finishing with
+/// the merged result and everything the sink collected.
+pub fn run_input<T, I, W, M>(
+    input: &Input,
+    segment_size: usize,
+    on_error: OnError,
+    init: I,
+    work: W,
+    merge: M,
+) -> Result<Aggregate<T>, Error>
+where
+    T: Send,
+    I: Fn() -> T + Sync,
+    W: Fn(&[u8], &mut T, &mut ErrorSink) -> Result<(), ParseError> + Sync,
+    M: FnOnce(Vec<T>) -> T,
+{
+    let mut sinks = [ErrorSink::new(on_error, input.bytes().unwrap_or_default())];
+    let work = |_, segment: &[u8], result: &mut T, errors: &mut ErrorSink| work(segment, result, errors);
+    let result = run_inputs(&[input], segment_size, &mut sinks, init, work, merge);
+    let (result, workers) = result.map_err(|(_, err)| match err {
+        Error::Parse(err) => input.locate(err).map_or_else(Error::Io, Error::Parse),
+        err => err,
+    })?;
+    let [mut errors] = sinks;
+    for stats in workers {
+        errors.record(stats);
+    }
+    Ok(errors.finish_by(|range| input.count_lines(range), result)?)
+}
+
+/// Like [`run_many`] over opened inputs, some of which may be files read with
+/// [`Backend::Read`](crate::input::Backend::Read): every worker reads the segments it
+/// claims from those into a buffer of its own, which holds one segment at a time.
+/// Sinks of such files see offsets within the file all the same.
+///
+/// Fails with the first error of the earliest failing input, along with its index; a
+/// failed read counts as an error at the start of its input.
+pub fn run_inputs<T, I, W, M>(
+    inputs: &[&Input],
+    segment_size: usize,
+    sinks: &mut [ErrorSink],
+    init: I,
+    work: W,
+    merge: M,
+) -> Result<(T, Vec<WorkerStats>), (usize, Error)>
+where
+    T: Send,
+    I: Fn() -> T + Sync,
+    W: Fn(usize, &[u8], &mut T, &mut ErrorSink) -> Result<(), ParseError> + Sync,
+    M: FnOnce(Vec<T>) -> T,
+{
+    assert_eq!(inputs.len(), sinks.len(), "one sink per input");
+    let claims = Claims::new(inputs.iter().map(|input| input.len()), segment_size);
+    let parents = &*sinks;
+    let workers: Vec<_> = (0..rayon::current_num_threads())
+        .into_par_iter()
+        .map(|_| {
+            let mut errors: Vec<_> = parents.iter().map(ErrorSink::fork).collect();
+            let mut result = init();
+            let mut stats = WorkerStats::default();
+            let mut buffer = None;
+            while let Some((index, claim)) = claims.next() {
+                let start = Instant::now();
+                let segment = match (inputs[index], inputs[index].bytes()) {
+                    (_, Some(bytes)) => lines(bytes, claim),
+                    (Input::File(file), None) => {
+                        let buffer = buffer.get_or_insert_with(|| Buffer::zeroed(TAIL));
+                        match file.read_lines(claim, buffer) {
+                            Ok((offset, segment)) => {
+                                errors[index].rebase(segment, offset);
+                                segment
+                            }
+                            Err(err) => {
+                                claims.stop();
+                                return Err((index, Error::Io(err)));
+                            }
+                        }
+                    }
+                    _ => unreachable!("inputs without bytes are files"),
+                };
+                if segment.is_empty() {
+                    continue;
+                }
+                if let Err(err) = work(index, segment, &mut result, &mut errors[index]) {
+                    claims.stop();
+                    return Err((index, Error::Parse(err)));
+                }
+                stats.segments += 1;
+                stats.bytes += segment.len();
+                stats.busy += start.elapsed();
+            }
+            Ok((result, errors, stats))
+        })
+        .collect();
+
+    let mut done = Vec::with_capacity(workers.len());
+    let mut failed: Option<(usize, Error)> = None;
+    let key = |index: usize, err: &Error| match err {
+        Error::Parse(err) => (index, err.offset),
+        Error::Io(_) => (index, 0),
+    };
+    for worker in workers {
+        match worker {
+            Ok(worker) => done.push(worker),
+            Err((index, err)) => {
+                if failed.as_ref().is_none_or(|(i, e)| key(index, &err) < key(*i, e)) {
+                    failed = Some((index, err));
+                }
+            }
+        }
+    }
+    match failed {
+        Some(failed) => Err(failed),
+        None => Ok(collect(done, sinks, merge)),
+    }
+}
+
+/// Merges the sinks of finished workers into `sinks` and their results with `merge`.
+fn collect<T, M, I>(workers: I, sinks: &mut [ErrorSink], merge: M) -> (T, Vec<WorkerStats>)
+where
+    I: IntoIterator<Item = (T, Vec<ErrorSink>, WorkerStats)>,
+    M: FnOnce(Vec<T>) -> T,
+{
+    let workers = workers.into_iter();
+    let mut tables = Vec::with_capacity(workers.size_hint().0);
+    let mut telemetry = Vec::with_capacity(workers.size_hint().0);
+    for (table, errors, stats) in workers {
+        for (sink, errors) in sinks.iter_mut().zip(errors) {
+            sink.merge(errors);
+        }
+        tables.push(table);
+        telemetry.push(stats);
+    }
+    (merge(tables), telemetry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::OnError;
+
+    #[test]
+    fn segments_cover_the_input_once() {
+        let mut input = Vec::new();
+        for i in 0..300 {
+            input.extend_from_slice(format!("{};{}.0\n", "x".repeat(i % 40), i % 50).as_bytes());
+        }
+        for trailing_newline in [true, false] {
+            if !trailing_newline {
+                input.pop();
+            }
+            for segment_size in [1, 5, 64, 999, input.len(), usize::MAX] {
+                let scheduler = Scheduler::new(&input, segment_size);
+                let mut expected = 0;
+                while let Some(segment) = scheduler.next() {
+                    let start = segment.as_ptr() as usize - input.as_ptr() as usize;
+                    assert_eq!(start, expected, "{} byte segments", segment_size);
+                    assert!(segment.ends_with(b"\n") || start + segment.len() == input.len());
+                    expected += segment.len();
+                }
+                assert_eq!(expected, input.len(), "{} byte segments", segment_size);
+            }
+        }
+    }
+
+    #[test]
+    fn segments_of_many_inputs_stay_within_their_input() {
+        let inputs = ["", "a;1.0\nb;2.0\n", "", "c;3.0", "d;4.0\ne;5.0\nf;6.0\n"].map(str::as_bytes);
+        for segment_size in [1, 7, 1000] {
+            let scheduler = Scheduler::many(inputs.to_vec(), segment_size);
+            let mut seen = vec![Vec::new(); inputs.len()];
+            while let Some((index, segment)) = scheduler.next_in() {
+                seen[index].extend_from_slice(segment);
+            }
+            let seen: Vec<&[u8]> = seen.iter().map(Vec::as_slice).collect();
+            assert_eq!(seen, inputs, "{} byte segments", segment_size);
+        }
+    }
+
+    #[test]
+    fn telemetry_accounts_for_every_segment() {
+        let input = "Abha;1.0\n".repeat(1000);
+        for threads in [1, 3] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let mut errors = ErrorSink::new(OnError::Fail, input.as_bytes());
+            let lines = pool
+                .install(|| {
+                    run(
+                        input.as_bytes(),
+                        100,
+                        &mut errors,
+                        || 0,
+                        |segment, lines, _| {
+                            *lines += segment.iter().filter(|&&b| b == b'\n').count();
+                            Ok(())
+                        },
+                        |lines| lines.into_iter().sum(),
+                    )
+                })
+                .unwrap();
+            let workers = errors.finish(input.as_bytes(), ()).workers;
+            assert_eq!(lines, 1000);
+            assert_eq!(workers.len(), threads);
+            assert_eq!(workers.iter().map(|w| w.bytes).sum::<usize>(), input.len());
+            // Every 100-byte claim starts exactly one segment.
+            let segments: usize = workers.iter().map(|w| w.segments).sum();
+            assert_eq!(segments, input.len().div_ceil(100));
+        }
+    }
+}
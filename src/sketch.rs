@@ -0,0 +1,409 @@
+//! Approximate, mergeable per-station summaries for values that do not fit a histogram.
+//!
+//! [`Histogram`](crate::histogram::Histogram) is exact because 1BRC temperatures are
+//! tenths in a fixed range. Readings with more precision or a wider range need sketches
+//! instead: a [`Kll`] sketch for quantiles and a [`HyperLogLog`] for the number of
+//! distinct readings. Both take plain `f64` values, merge across workers like any other
+//! [`Reducer`], and have a size independent of the number of values. [`Sketch`]
+//! bundles them with [`DecimalStats`] for use in a station table, and reads any decimal
+//! rather than only 1BRC temperatures.
+
+use std::collections::BTreeMap;
+
+use crate::error::{Aggregate, OnError, ParseError};
+use crate::generate::Rng;
+use crate::reduce;
+use crate::stats::Reducer;
+
+/// Default accuracy parameter of [`Kll`].
+pub const KLL_K: usize = 200;
+/// Register index bits of [`HyperLogLog`]; 4096 one-byte registers.
+pub const HLL_PRECISION: u32 = 12;
+const HLL_REGISTERS: usize = 1 << HLL_PRECISION;
+
+/// Quantile sketch of Karnin, Lang and Liberty (2016).
+///
+/// Values are kept in levels of compactors, a value at level `h` standing for `2^h`
+/// inputs. When the sketch outgrows its budget, a full level is sorted and every other
+/// value, starting at a random one of the first two, moves up a level. Level capacities
+/// shrink by 2/3 per level below the top, so the sketch holds about `3k` values however
+/// many it has seen.
+///
+/// The rank of a returned quantile is off by less than about `1.65%` of the count with
+/// 99% confidence at the default `k = 200`, which is the bound the DataSketches library
+/// documents for the same construction; the error shrinks in proportion to `1 / k`.
+/// Sketches with fewer than `k` values are exact.
+#[derive(Debug, Clone)]
+pub struct Kll {
+    k: usize,
+    levels: Vec<Vec<f64>>,
+    /// Values held across all levels, and how many the current levels may hold.
+    size: usize,
+    max_size: usize,
+    count: u64,
+    rng: Rng,
+}
+
+impl Default for Kll {
+    fn default() -> Self {
+        Kll::new(KLL_K)
+    }
+}
+
+impl Kll {
+    /// Panics if `k` is below 8.
+    pub fn new(k: usize) -> Self {
+        assert!(k >= 8, "k must be at least 8");
+        let mut kll = Kll {
+            k,
+            levels: Vec::new(),
+            size: 0,
+            max_size: 0,
+            count: 0,
+            rng: Rng::new(0x6b6c_6c5f_7365_6564),
+        };
+        kll.grow();
+        kll
+    }
+
+    /// Adds a value; NaN is ignored.
+    #[inline]
+    pub fn insert(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        self.levels[0].push(value);
+        self.size += 1;
+        self.count += 1;
+        if self.size >= self.max_size {
+            self.compress();
+        }
+    }
+
+    /// Number of values inserted, including those of merged sketches.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Approximately the `p`th percentile by nearest rank, like
+    /// [`Histogram::percentile`](crate::histogram::Histogram::percentile).
+    ///
+    /// Returns `None` for an empty sketch. Panics unless `p` is within `0..=100`.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        assert!((0.0..=100.0).contains(&p), "percentile {} is not within 0..=100", p);
+        let mut weighted: Vec<(f64, u64)> = self
+            .levels
+            .iter()
+            .enumerate()
+            .flat_map(|(h, level)| level.iter().map(move |&value| (value, 1 << h)))
+            .collect();
+        weighted.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        let total: u64 = weighted.iter().map(|&(_, weight)| weight).sum();
+        let rank = ((p * total as f64 / 100.0).ceil() as u64).clamp(1, total.max(1));
+        let mut seen = 0;
+        weighted.into_iter().find_map(|(value, weight)| {
+            seen += weight;
+            (seen >= rank).then_some(value)
+        })
+    }
+
+    /// Adds the values of `other`, as if they had been inserted here.
+    pub fn merge(&mut self, other: &Kll) {
+        while self.levels.len() < other.levels.len() {
+            self.grow();
+        }
+        for (level, other) in self.levels.iter_mut().zip(&other.levels) {
+            level.extend_from_slice(other);
+        }
+        self.size = self.levels.iter().map(Vec::len).sum();
+        self.count += other.count;
+        while self.size >= self.max_size {
+            self.compress();
+        }
+    }
+
+    fn capacity(&self, level: usize) -> usize {
+        let depth = self.levels.len() - level - 1;
+        (self.k as f64 * (2.0f64 / 3.0).powi(depth as i32)).ceil() as usize + 1
+    }
+
+    fn grow(&mut self) {
+        self.levels.push(Vec::new());
+        self.max_size = (0..self.levels.len()).map(|h| self.capacity(h)).sum();
+    }
+
+    fn compress(&mut self) {
+        let mut h = 0;
+        while h < self.levels.len() {
+            if self.levels[h].len() >= self.capacity(h) {
+                if h + 1 == self.levels.len() {
+                    self.grow();
+                }
+                let mut level = std::mem::take(&mut self.levels[h]);
+                level.sort_unstable_by(|a, b| a.total_cmp(b));
+                // An odd value out stays behind at this level.
+                let keep = if level.len() % 2 == 1 { level.pop() } else { None };
+                let offset = (self.rng.next_u64() & 1) as usize;
+                self.levels[h + 1].extend(level.iter().skip(offset).step_by(2));
+                level.clear();
+                level.extend(keep);
+                self.levels[h] = level;
+                self.size = self.levels.iter().map(Vec::len).sum();
+                if self.size < self.max_size {
+                    break;
+                }
+            }
+            h += 1;
+        }
+    }
+}
+
+/// Distinct-count estimator of Flajolet et al. (2007) with `2^12` registers.
+///
+/// Values are told apart by their bit pattern, so `0.0` and `-0.0` count as two. The
+/// relative standard error is `1.04 / sqrt(4096)`, about `1.6%`, and counts up to about
+/// 10,000 use linear counting, which is considerably tighter; the exact distinct count
+/// of a station with 1BRC readings is at most 1999.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    registers: Box<[u8; HLL_REGISTERS]>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        HyperLogLog {
+            registers: vec![0; HLL_REGISTERS].into_boxed_slice().try_into().unwrap(),
+        }
+    }
+}
+
+impl HyperLogLog {
+    #[inline]
+    pub fn insert(&mut self, value: f64) {
+        let hash = mix(value.to_bits());
+        let index = (hash >> (64 - HLL_PRECISION)) as usize;
+        // The sentinel bit bounds the run of zeros for a hash whose low bits are all zero.
+        let rest = (hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        let register = &mut self.registers[index];
+        *register = (*register).max(rank);
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        for (register, &other) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *register = (*register).max(other);
+        }
+    }
+
+    /// Estimated number of distinct values inserted.
+    pub fn estimate(&self) -> u64 {
+        let m = HLL_REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&r| (-(r as i32) as f64).exp2()).sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
+
+/// The 64-bit finalizer of MurmurHash3, so that nearby values land far apart.
+#[inline]
+fn mix(mut x: u64) -> u64 {
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    x ^ (x >> 33)
+}
+
+/// Running statistics of one station over readings of any precision.
+///
+/// The mean and variance are kept with Welford's updates and merged with the pairwise
+/// formula of Chan et al., so they stay accurate however the input is split.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecimalStats {
+    pub min: f64,
+    pub max: f64,
+    pub count: u64,
+    mean: f64,
+    /// Sum of the squared deviations from the mean.
+    m2: f64,
+}
+
+impl Default for DecimalStats {
+    fn default() -> Self {
+        DecimalStats {
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+}
+
+impl DecimalStats {
+    #[inline]
+    pub fn update(&mut self, value: f64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn merge(&mut self, other: &DecimalStats) {
+        if other.count == 0 {
+            return;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        let weight = other.count as f64 / count as f64;
+        self.mean += delta * weight;
+        self.m2 += other.m2 + delta * delta * self.count as f64 * weight;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.count = count;
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// The population variance.
+    pub fn variance(&self) -> f64 {
+        self.m2 / self.count as f64
+    }
+
+    /// The population standard deviation.
+    pub fn stddev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+}
+
+/// Sketches keyed by station name, in output order.
+pub type Sketches = BTreeMap<String, Sketch>;
+
+/// Statistics plus approximate quantiles and distinct count of one station, about 9 KiB
+/// per station and worker.
+#[derive(Debug, Clone, Default)]
+pub struct Sketch {
+    pub stats: DecimalStats,
+    pub quantiles: Kll,
+    pub distinct: HyperLogLog,
+}
+
+impl Reducer for Sketch {
+    type Value = f64;
+    type Output = Sketch;
+
+    fn init() -> Self {
+        Sketch::default()
+    }
+
+    #[inline]
+    fn update(&mut self, value: f64) {
+        self.stats.update(value);
+        self.quantiles.insert(value);
+        self.distinct.insert(value);
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.stats.merge(&other.stats);
+        self.quantiles.merge(&other.quantiles);
+        self.distinct.merge(&other.distinct);
+    }
+
+    fn finish(&self) -> Sketch {
+        self.clone()
+    }
+}
+
+/// Aggregates `input` into per-station sketches with the scanner and scheduling of
+/// approach 9.
+pub fn aggregate_with(input: &[u8], on_error: OnError) -> Result<Aggregate<Sketches>, ParseError> {
+    reduce::aggregate::<Sketch>(input, on_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    /// Rank of `value` among `sorted` as a fraction: where it sits between the values
+    /// below it and those up to and including it.
+    fn rank_error(sorted: &[f64], p: f64, value: f64) -> f64 {
+        let target = p / 100.0;
+        let below = sorted.partition_point(|&v| v < value) as f64 / sorted.len() as f64;
+        let upto = sorted.partition_point(|&v| v <= value) as f64 / sorted.len() as f64;
+        (below - target).max(0.0).max(target - upto)
+    }
+
+    #[test]
+    fn kll_ranks_stay_within_the_bound_when_merged() {
+        let mut rng = Rng::new(7);
+        let values: Vec<f64> = (0..500_000).map(|_| rng.gaussian() * 13.7 + rng.next_f64()).collect();
+        let mut whole = Kll::default();
+        for &value in &values {
+            whole.insert(value);
+        }
+        let mut merged = Kll::default();
+        for chunk in values.chunks(23_000) {
+            let mut part = Kll::default();
+            for &value in chunk {
+                part.insert(value);
+            }
+            merged.merge(&part);
+        }
+        let mut sorted = values.clone();
+        sorted.sort_unstable_by(|a, b| a.total_cmp(b));
+        for kll in [&whole, &merged] {
+            assert_eq!(kll.count(), values.len() as u64);
+            assert!(kll.size < 4 * KLL_K, "{} values held", kll.size);
+            for p in [0.0, 1.0, 10.0, 25.0, 50.0, 75.0, 90.0, 99.0, 100.0] {
+                let value = kll.percentile(p).unwrap();
+                let error = rank_error(&sorted, p, value);
+                assert!(error < 0.0165, "p{} = {} is {:.4} off in rank", p, value, error);
+            }
+        }
+        assert_eq!(Kll::default().percentile(50.0), None);
+    }
+
+    #[test]
+    fn small_kll_sketches_are_exact() {
+        let mut kll = Kll::default();
+        for value in [4.0, 1.5, f64::NAN, -3.0, 2.0] {
+            kll.insert(value);
+        }
+        assert_eq!(kll.count(), 4);
+        assert_eq!(kll.percentile(50.0), Some(1.5));
+        assert_eq!(kll.percentile(100.0), Some(4.0));
+    }
+
+    #[test]
+    fn distinct_counts_stay_within_the_bound() {
+        let mut rng = Rng::new(11);
+        for n in [0, 1, 100, 1999, 50_000, 1_000_000] {
+            let values: HashSet<u64> = (0..n).map(|_| rng.next_u64()).collect();
+            let mut halves = [HyperLogLog::default(), HyperLogLog::default()];
+            for (i, &value) in values.iter().enumerate() {
+                // Every value twice, so that repeats are seen across the merge.
+                halves[i % 2].insert(f64::from_bits(value >> 12));
+                halves[(i + 1) % 2].insert(f64::from_bits(value >> 12));
+            }
+            let [mut merged, other] = halves;
+            merged.merge(&other);
+            let estimate = merged.estimate() as f64;
+            let exact = values.len() as f64;
+            // Three standard errors of 1.6%.
+            let allowed = (exact * 0.05).max(1.0);
+            assert!((estimate - exact).abs() <= allowed, "{} estimated as {}", exact, estimate);
+        }
+    }
+}
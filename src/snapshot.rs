@@ -0,0 +1,170 @@
+//! Binary snapshots of aggregated stations, so that shards aggregated on different
+//! machines or in different runs can be combined into the final result.
+//!
+//! A snapshot is a header followed by one record per station, in name order:
+//!
+//! ```text
+//! header:  b"1BRCSNAP"  version: u8  stations: u64
+//! station: name_len: u8  name: [u8; name_len]  min: i16  max: i16  sum: i64  sum_sq: u64
+//!          count: u64
+//! ```
+//!
+//! All integers are little-endian and temperatures are in tenths of a degree, exactly as
+//! in [`StationStats`], so loading a snapshot gives back the stations that were saved.
+//! This is version 2; version 1 had no `sum_sq` and is rejected.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::parse::MAX_NAME_LEN;
+use crate::stats::{merge_stations, StationStats, Stations};
+
+const MAGIC: &[u8; 8] = b"1BRCSNAP";
+const VERSION: u8 = 2;
+
+/// Writes `stations` to `writer` as a snapshot.
+pub fn save<W: Write>(stations: &Stations, mut writer: W) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[VERSION])?;
+    writer.write_all(&(stations.len() as u64).to_le_bytes())?;
+    for (name, stats) in stations {
+        if name.len() > MAX_NAME_LEN {
+            return Err(invalid(format!("station name is longer than {} bytes", MAX_NAME_LEN)));
+        }
+        writer.write_all(&[name.len() as u8])?;
+        writer.write_all(name.as_bytes())?;
+        writer.write_all(&stats.min.to_le_bytes())?;
+        writer.write_all(&stats.max.to_le_bytes())?;
+        writer.write_all(&stats.sum.to_le_bytes())?;
+        writer.write_all(&stats.sum_sq.to_le_bytes())?;
+        writer.write_all(&stats.count.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+/// Reads a snapshot written by [`save`]. Anything else, including a truncated snapshot or
+/// one with bytes after its last station, is an [`io::ErrorKind::InvalidData`] error.
+pub fn load<R: Read>(mut reader: R) -> io::Result<Stations> {
+    let mut magic = [0; 8];
+    read(&mut reader, &mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a station snapshot"));
+    }
+    let [version] = bytes(&mut reader)?;
+    if version != VERSION {
+        return Err(invalid(format!("unsupported snapshot version {}", version)));
+    }
+    let len = u64::from_le_bytes(bytes(&mut reader)?);
+
+    let mut stations = Stations::new();
+    for _ in 0..len {
+        let [name_len] = bytes(&mut reader)?;
+        let mut name = vec![0; name_len as usize];
+        read(&mut reader, &mut name)?;
+        let name = String::from_utf8(name).map_err(|_| invalid("station name is not valid UTF-8"))?;
+        let stats = StationStats {
+            min: i16::from_le_bytes(bytes(&mut reader)?),
+            max: i16::from_le_bytes(bytes(&mut reader)?),
+            sum: i64::from_le_bytes(bytes(&mut reader)?),
+            sum_sq: u64::from_le_bytes(bytes(&mut reader)?),
+            count: u64::from_le_bytes(bytes(&mut reader)?),
+        };
+        let valid = -999..=999;
+        if name.len() > MAX_NAME_LEN
+            || stats.count == 0
+            || stats.min > stats.max
+            || !valid.contains(&stats.min)
+            || !valid.contains(&stats.max)
+        {
+            return Err(invalid(format!("corrupt statistics for station `{}`", name)));
+        }
+        if stations.insert(name, stats).is_some() {
+            return Err(invalid("duplicate station"));
+        }
+    }
+    if reader.read(&mut [0])? != 0 {
+        return Err(invalid("trailing bytes after the last station"));
+    }
+    Ok(stations)
+}
+
+pub fn save_file(stations: &Stations, path: &Path) -> io::Result<()> {
+    save(stations, BufWriter::new(File::create(path)?))
+}
+
+pub fn load_file(path: &Path) -> io::Result<Stations> {
+    load(BufReader::new(File::open(path)?))
+}
+
+/// Combines partial results into one, as if their inputs had been aggregated together.
+pub fn merge<I: IntoIterator<Item = Stations>>(partials: I) -> Stations {
+    let mut total = Stations::new();
+    for partial in partials {
+        merge_stations(&mut total, partial);
+    }
+    total
+}
+
+fn read<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<()> {
+    reader.read_exact(buf).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => invalid("snapshot is truncated"),
+        _ => err,
+    })
+}
+
+fn bytes<const N: usize, R: Read>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    read(reader, &mut buf)?;
+    Ok(buf)
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stations() -> Stations {
+        let mut stations = Stations::new();
+        stations.insert("Abha".to_string(), StationStats { min: -999, max: 999, sum: 12, sum_sq: 1_996_146, count: 3 });
+        stations.insert("St. John's".to_string(), StationStats::new(152));
+        stations.insert("é".repeat(50), StationStats { min: 0, max: 0, sum: 0, sum_sq: 0, count: u64::MAX });
+        stations
+    }
+
+    #[test]
+    fn snapshots_round_trip() {
+        for stations in [Stations::new(), stations()] {
+            let mut bytes = Vec::new();
+            save(&stations, &mut bytes).unwrap();
+            assert_eq!(load(&bytes[..]).unwrap(), stations);
+        }
+    }
+
+    #[test]
+    fn damaged_snapshots_are_rejected() {
+        let mut bytes = Vec::new();
+        save(&stations(), &mut bytes).unwrap();
+        let mut damaged = vec![
+            bytes[..bytes.len() - 1].to_vec(),
+            [&bytes[..], b"x"].concat(),
+            [b"1BRCSNAX", &bytes[8..]].concat(),
+        ];
+        for other in [VERSION - 1, VERSION + 1] {
+            let mut version = bytes.clone();
+            version[8] = other;
+            damaged.push(version);
+        }
+        for damaged in damaged {
+            let err = load(&damaged[..]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", err);
+        }
+
+        let mut long = Stations::new();
+        long.insert("a".repeat(MAX_NAME_LEN + 1), StationStats::new(1));
+        assert!(save(&long, &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+
+use crate::parse::Reading;
+
+/// Running statistics of one station. Temperatures are kept in tenths of a degree.
+///
+/// Sums are exact integers, so merging the statistics of any split of the input gives
+/// exactly the statistics of the whole, and the variance is only rounded once, when it
+/// is asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StationStats {
+    pub min: i16,
+    pub max: i16,
+    pub sum: i64,
+    /// Sum of the squared temperatures, in hundredths of a square degree. A square is at
+    /// most 998,001, so this cannot overflow before a station has seen 18 trillion values.
+    pub sum_sq: u64,
+    pub count: u64,
+}
+
+/// Aggregated measurements keyed by station name, ordered the way the 1BRC output expects.
+pub type Stations = BTreeMap<String, StationStats>;
+
+impl Default for StationStats {
+    fn default() -> Self {
+        StationStats {
+            min: i16::MAX,
+            max: i16::MIN,
+            sum: 0,
+            sum_sq: 0,
+            count: 0,
+        }
+    }
+}
+
+impl StationStats {
+    #[inline]
+    pub fn new(value: i16) -> Self {
+        StationStats {
+            min: value,
+            max: value,
+            sum: value as i64,
+            sum_sq: square(value),
+            count: 1,
+        }
+    }
+
+    #[inline]
+    pub fn update(&mut self, value: i16) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value as i64;
+        self.sum_sq += square(value);
+        self.count += 1;
+    }
+
+    #[inline]
+    pub fn merge(&mut self, other: &StationStats) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.sum_sq += other.sum_sq;
+        self.count += other.count;
+    }
+
+    #[inline]
+    pub fn mean(&self) -> f64 {
+        self.sum as f64 / 10.0 / self.count as f64
+    }
+
+    /// The mean in tenths of a degree, rounded half up like the Java reference.
+    #[inline]
+    pub fn mean_tenths(&self) -> i64 {
+        let count = self.count as i64;
+        (2 * self.sum + count).div_euclid(2 * count)
+    }
+
+    /// The population variance, in square degrees.
+    pub fn variance(&self) -> f64 {
+        // n² times the variance in square tenths, which is exact in 128 bits.
+        let n = self.count as i128;
+        let scaled = n * self.sum_sq as i128 - (self.sum as i128).pow(2);
+        scaled as f64 / (n * n) as f64 / 100.0
+    }
+
+    /// The population standard deviation, in degrees.
+    pub fn stddev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+}
+
+#[inline]
+pub(crate) fn square(value: i16) -> u64 {
+    (value as i32 * value as i32) as u64
+}
+
+/// Where a row sits in the aggregated inputs: the index of its input, then the byte
+/// offset of its line within that input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Position {
+    pub input: usize,
+    pub offset: usize,
+}
+
+impl Position {
+    /// After every row of every input; where values without a position go.
+    pub const END: Position = Position { input: usize::MAX, offset: usize::MAX };
+}
+
+/// What a station table keeps per station: filled one value at a time by the scanners,
+/// folded together across workers, and turned into the reported value at the end.
+///
+/// Values are 1BRC temperatures in tenths for most reducers; those that take readings
+/// in any decimal form, like [`Sketch`](crate::sketch::Sketch), read `f64` instead.
+///
+/// Scanners are generic over the reducer, so each one is compiled into its own copy of
+/// the hot loop with `update` inlined, as if it had been written there by hand.
+pub trait Reducer: Clone + Send + Sync {
+    type Value: Reading;
+    type Output;
+
+    /// The state of a station before its first value.
+    fn init() -> Self;
+
+    fn update(&mut self, value: Self::Value);
+
+    /// Like [`update`](Reducer::update), for a value read at `at`. Workers see rows out
+    /// of order, so reducers that depend on the order, like the first value of a
+    /// station, override this and merge by position instead. The position costs nothing
+    /// to reducers that ignore it.
+    #[inline]
+    fn update_at(&mut self, value: Self::Value, at: Position) {
+        let _ = at;
+        self.update(value);
+    }
+
+    fn merge(&mut self, other: &Self);
+
+    fn finish(&self) -> Self::Output;
+}
+
+impl Reducer for StationStats {
+    type Value = i16;
+    type Output = StationStats;
+
+    #[inline]
+    fn init() -> Self {
+        StationStats::default()
+    }
+
+    #[inline]
+    fn update(&mut self, value: i16) {
+        StationStats::update(self, value);
+    }
+
+    #[inline]
+    fn merge(&mut self, other: &Self) {
+        StationStats::merge(self, other);
+    }
+
+    fn finish(&self) -> StationStats {
+        *self
+    }
+}
+
+/// Adds every station of `other` to `into`.
+pub fn merge_stations(into: &mut Stations, other: Stations) {
+    for (name, stats) in other {
+        into.entry(name)
+            .and_modify(|e| e.merge(&stats))
+            .or_insert(stats);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variance_survives_any_split() {
+        let values: Vec<i16> = (0..1000).map(|i| ((i * 7919) % 1999 - 999) as i16).collect();
+        let mean = values.iter().map(|&v| v as f64 / 10.0).sum::<f64>() / values.len() as f64;
+        let expected = values
+            .iter()
+            .map(|&v| (v as f64 / 10.0 - mean).powi(2))
+            .sum::<f64>()
+            / values.len() as f64;
+
+        let mut whole = StationStats::default();
+        values.iter().for_each(|&v| whole.update(v));
+        assert!((whole.variance() - expected).abs() < 1e-9, "{} != {}", whole.variance(), expected);
+        for split in [1, 10, 999] {
+            let mut merged = StationStats::new(values[0]);
+            let mut rest = StationStats::default();
+            values[1..split].iter().for_each(|&v| merged.update(v));
+            values[split..].iter().for_each(|&v| rest.update(v));
+            merged.merge(&rest);
+            assert_eq!(merged, whole);
+        }
+        assert_eq!(StationStats::new(-999).stddev(), 0.0);
+    }
+}
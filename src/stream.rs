@@ -0,0 +1,147 @@
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+
+use crate::aggregator::Aggregator;
+use crate::delim::count;
+use crate::error::{Aggregate, Error, OnError, MAX_REPORTED};
+use crate::stats::merge_stations;
+
+/// Bytes read before a buffer is handed to the aggregator; large enough that every worker
+/// of the parallel approaches gets several segments out of it.
+pub const BUFFER_SIZE: usize = 64 << 20;
+
+/// Buffers read ahead while the aggregator works on the current one.
+const READ_AHEAD: usize = 2;
+
+/// Whole lines of the stream, and where they start in it.
+struct Chunk {
+    bytes: Vec<u8>,
+    offset: usize,
+    /// Lines before the chunk.
+    lines: u64,
+}
+
+/// Aggregates everything `reader` yields, reading it in `buffer_size` pieces on a
+/// separate thread while `aggregator` works on the previous piece.
+///
+/// A piece ends after its last `\n` and the partial line behind it is carried over to the
+/// next one, so the aggregator only ever sees whole lines; a line longer than a piece
+/// grows it. Errors report offsets and line numbers within the whole stream, and the
+/// result is the same as aggregating all of it at once.
+pub fn aggregate_reader<A, R>(
+    aggregator: &A,
+    reader: R,
+    on_error: OnError,
+    buffer_size: usize,
+) -> Result<Aggregate, Error>
+where
+    A: Aggregator + ?Sized,
+    R: Read + Send,
+{
+    let (chunks, received) = mpsc::sync_channel(READ_AHEAD);
+    let (recycle, free) = mpsc::channel();
+    let stop = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        let reader = Stoppable { reader, stop: &stop };
+        let reader = scope.spawn(move || read_chunks(reader, buffer_size, chunks, free));
+        let mut total = Aggregate::default();
+        for chunk in received {
+            let aggregate = aggregator.aggregate_with(&chunk.bytes, on_error).map_err(|mut err| {
+                // The scope waits for the reader, which must not read the rest of the
+                // stream first.
+                stop.store(true, Ordering::Relaxed);
+                err.offset += chunk.offset;
+                err.line += chunk.lines;
+                err
+            })?;
+            add(&mut total, aggregate, &chunk);
+            let _ = recycle.send(chunk.bytes);
+        }
+        reader.join().expect("stream reader panicked")?;
+        Ok(total)
+    })
+}
+
+fn add(total: &mut Aggregate, aggregate: Aggregate, chunk: &Chunk) {
+    merge_stations(&mut total.stations, aggregate.stations);
+    let room = MAX_REPORTED - total.errors.len();
+    total.errors.extend(aggregate.errors.into_iter().take(room).map(|mut err| {
+        err.offset += chunk.offset;
+        err.line += chunk.lines;
+        err
+    }));
+    total.rejected += aggregate.rejected;
+    if total.workers.len() < aggregate.workers.len() {
+        total.workers.resize(aggregate.workers.len(), Default::default());
+    }
+    for (total, worker) in total.workers.iter_mut().zip(aggregate.workers) {
+        total.segments += worker.segments;
+        total.bytes += worker.bytes;
+        total.busy += worker.busy;
+    }
+}
+
+/// A reader that reports the end of its input once `stop` is set.
+struct Stoppable<'a, R> {
+    reader: R,
+    stop: &'a AtomicBool,
+}
+
+impl<R: Read> Read for Stoppable<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.stop.load(Ordering::Relaxed) {
+            return Ok(0);
+        }
+        self.reader.read(buf)
+    }
+}
+
+fn read_chunks<R: Read>(
+    mut reader: Stoppable<'_, R>,
+    buffer_size: usize,
+    chunks: mpsc::SyncSender<Chunk>,
+    free: mpsc::Receiver<Vec<u8>>,
+) -> io::Result<()> {
+    let mut buffer = Vec::with_capacity(buffer_size);
+    let mut size = buffer_size.max(1);
+    let mut offset = 0;
+    let mut lines = 0;
+    loop {
+        let want = size - buffer.len();
+        let read = (&mut reader).take(want as u64).read_to_end(&mut buffer)?;
+        if reader.stop.load(Ordering::Relaxed) {
+            // The aggregator stopped at an error.
+            return Ok(());
+        }
+        let eof = read < want;
+        let end = match buffer.iter().rposition(|&b| b == b'\n') {
+            Some(last) if !eof => last + 1,
+            None if !eof => {
+                // Not even one whole line yet.
+                size *= 2;
+                continue;
+            }
+            _ => buffer.len(),
+        };
+
+        let mut next = free.try_recv().unwrap_or_else(|_| Vec::with_capacity(size));
+        next.clear();
+        next.extend_from_slice(&buffer[end..]);
+        buffer.truncate(end);
+        let chunk_lines = count(&buffer, b'\n') as u64;
+        if !buffer.is_empty() {
+            let chunk = Chunk { bytes: buffer, offset, lines };
+            if chunks.send(chunk).is_err() {
+                // The aggregator stopped at an error.
+                return Ok(());
+            }
+        }
+        if eof {
+            return Ok(());
+        }
+        offset += end;
+        lines += chunk_lines;
+        buffer = next;
+    }
+}
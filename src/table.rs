@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+use std::hash::Hasher;
+
+use crate::error::ParseErrorKind;
+use crate::merge::merge_tables;
+use crate::parse::station_name;
+use crate::stats::{Position, Reducer, StationStats, Stations};
+
+const EMPTY: u32 = u32::MAX;
+
+/// Hash used to place keys in a [`StationTable`]; it is deterministic so tables
+/// filled on different threads agree on it.
+#[inline]
+pub fn hash_key(key: &[u8]) -> u64 {
+    let mut hasher = ahash::AHasher::default();
+    hasher.write(key);
+    hasher.finish()
+}
+
+#[derive(Debug, Clone)]
+struct Entry<V> {
+    hash: u64,
+    key_start: u32,
+    key_len: u32,
+    value: V,
+}
+
+/// Open-addressing table keyed by station name bytes.
+///
+/// Slots only hold an index into a dense entry list, and key bytes are copied once into
+/// an arena owned by the table, so the lookup path never allocates. Collisions are
+/// resolved by linear probing with a full key comparison, and the slot array doubles
+/// once it is half full.
+#[derive(Debug, Clone)]
+pub struct StationTable<V> {
+    slots: Vec<u32>,
+    mask: usize,
+    entries: Vec<Entry<V>>,
+    keys: Vec<u8>,
+}
+
+impl<V> Default for StationTable<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> StationTable<V> {
+    pub fn new() -> Self {
+        Self::with_capacity(1 << 10)
+    }
+
+    /// Creates a table with at least `capacity` slots, rounded up to a power of two.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.max(2).next_power_of_two();
+        StationTable {
+            slots: vec![EMPTY; capacity],
+            mask: capacity - 1,
+            entries: Vec::new(),
+            keys: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    #[inline]
+    fn key_of(&self, entry: &Entry<V>) -> &[u8] {
+        &self.keys[entry.key_start as usize..(entry.key_start + entry.key_len) as usize]
+    }
+
+    #[inline]
+    fn probe(&self, key: &[u8], hash: u64) -> Result<usize, usize> {
+        let mut idx = hash as usize & self.mask;
+        loop {
+            let slot = self.slots[idx];
+            if slot == EMPTY {
+                return Err(idx);
+            }
+            let entry = &self.entries[slot as usize];
+            if entry.hash == hash && self.key_of(entry) == key {
+                return Ok(slot as usize);
+            }
+            idx = (idx + 1) & self.mask;
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, key: &[u8]) -> Option<&V> {
+        self.probe(key, hash_key(key))
+            .ok()
+            .map(|i| &self.entries[i].value)
+    }
+
+    /// Returns the value stored for `key`, inserting `f()` first if the key is new.
+    /// `hash` must be `hash_key(key)`.
+    #[inline]
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: &[u8], hash: u64, f: F) -> &mut V {
+        let i = match self.probe(key, hash) {
+            Ok(i) => i,
+            Err(slot) => self.insert_at(slot, key, hash, f()),
+        };
+        &mut self.entries[i].value
+    }
+
+    /// Like [`get_or_insert_with`](Self::get_or_insert_with), but leaves the table
+    /// untouched when `f` fails.
+    #[inline]
+    pub fn try_get_or_insert_with<E, F: FnOnce() -> Result<V, E>>(
+        &mut self,
+        key: &[u8],
+        hash: u64,
+        f: F,
+    ) -> Result<&mut V, E> {
+        let i = match self.probe(key, hash) {
+            Ok(i) => i,
+            Err(slot) => self.insert_at(slot, key, hash, f()?),
+        };
+        Ok(&mut self.entries[i].value)
+    }
+
+    #[cold]
+    fn insert_at(&mut self, slot: usize, key: &[u8], hash: u64, value: V) -> usize {
+        let i = self.entries.len();
+        assert!(i < EMPTY as usize, "station table is full");
+        let key_start = u32::try_from(self.keys.len())
+            .ok()
+            .filter(|start| start.checked_add(key.len() as u32).is_some())
+            .expect("station table key storage is full");
+        self.keys.extend_from_slice(key);
+        self.entries.push(Entry {
+            hash,
+            key_start,
+            key_len: key.len() as u32,
+            value,
+        });
+        self.slots[slot] = i as u32;
+        if self.entries.len() * 2 > self.slots.len() {
+            self.grow();
+        }
+        i
+    }
+
+    fn grow(&mut self) {
+        let capacity = self.slots.len() * 2;
+        self.slots = vec![EMPTY; capacity];
+        self.mask = capacity - 1;
+        for (i, entry) in self.entries.iter().enumerate() {
+            let mut idx = entry.hash as usize & self.mask;
+            while self.slots[idx] != EMPTY {
+                idx = (idx + 1) & self.mask;
+            }
+            self.slots[idx] = i as u32;
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &V)> {
+        self.entries
+            .iter()
+            .map(move |entry| (self.key_of(entry), &entry.value))
+    }
+
+    /// Like [`iter`](Self::iter), with the stored `hash_key` of every key.
+    pub fn iter_hashed(&self) -> impl Iterator<Item = (u64, &[u8], &V)> {
+        self.entries
+            .iter()
+            .map(move |entry| (entry.hash, self.key_of(entry), &entry.value))
+    }
+
+    /// Folds every entry of `other` into `self`, touching only occupied entries.
+    pub fn merge<F: FnMut(&mut V, V)>(&mut self, other: StationTable<V>, mut f: F) {
+        let StationTable { entries, keys, .. } = other;
+        for entry in entries {
+            let key = &keys[entry.key_start as usize..(entry.key_start + entry.key_len) as usize];
+            match self.probe(key, entry.hash) {
+                Ok(i) => f(&mut self.entries[i].value, entry.value),
+                Err(slot) => {
+                    self.insert_at(slot, key, entry.hash, entry.value);
+                }
+            }
+        }
+    }
+}
+
+impl<V: Reducer> StationTable<V> {
+    /// Adds `value` to the station `key`; names are checked for UTF-8 the first time
+    /// they are seen, so the hot path stays free of validation.
+    #[inline]
+    pub fn update(&mut self, key: &[u8], hash: u64, value: V::Value) -> Result<(), ParseErrorKind> {
+        self.entry(key, hash)?.update(value);
+        Ok(())
+    }
+
+    /// Like [`update`](Self::update), for a value read at `at`.
+    #[inline]
+    pub fn update_at(&mut self, key: &[u8], hash: u64, value: V::Value, at: Position) -> Result<(), ParseErrorKind> {
+        self.entry(key, hash)?.update_at(value, at);
+        Ok(())
+    }
+
+    #[inline]
+    fn entry(&mut self, key: &[u8], hash: u64) -> Result<&mut V, ParseErrorKind> {
+        self.try_get_or_insert_with(key, hash, || station_name(key).map(|_| V::init()))
+    }
+
+    /// Merges per-worker tables in parallel; see [`merge_tables`].
+    pub fn merge_all(tables: Vec<StationTable<V>>) -> Self {
+        merge_tables(tables, V::merge)
+    }
+
+    /// The finished value of every station, in name order.
+    pub fn to_map(&self) -> BTreeMap<String, V::Output> {
+        self.iter()
+            .map(|(key, value)| (String::from_utf8_lossy(key).into_owned(), value.finish()))
+            .collect()
+    }
+}
+
+impl StationTable<StationStats> {
+    pub fn merge_stats(&mut self, other: StationTable<StationStats>) {
+        self.merge(other, |a, b| a.merge(&b));
+    }
+
+    pub fn to_stations(&self) -> Stations {
+        self.to_map()
+    }
+}
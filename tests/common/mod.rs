@@ -0,0 +1,88 @@
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use rs_1brc::generate::{self, Config, StationSet};
+use rs_1brc::{StationStats, Stations};
+
+/// Deliberately naive aggregator: no SIMD, no custom tables, no shortcuts.
+pub fn oracle(input: &[u8]) -> Stations {
+    values_by_station(input)
+        .into_iter()
+        .map(|(name, values)| {
+            let stats = StationStats {
+                min: *values.iter().min().unwrap(),
+                max: *values.iter().max().unwrap(),
+                sum: values.iter().map(|&v| v as i64).sum(),
+                sum_sq: values.iter().map(|&v| (v as i64 * v as i64) as u64).sum(),
+                count: values.len() as u64,
+            };
+            (name, stats)
+        })
+        .collect()
+}
+
+/// Every value of every station in tenths, in input order, straight from the text.
+pub fn values_by_station(input: &[u8]) -> BTreeMap<String, Vec<i16>> {
+    let text = std::str::from_utf8(input).expect("fixtures are UTF-8");
+    let mut stations: BTreeMap<String, Vec<i16>> = BTreeMap::new();
+    for line in text.split('\n').filter(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(';').expect("line without `;`");
+        let value: f64 = value.parse().expect("value is a number");
+        stations.entry(name.to_string()).or_default().push((value * 10.0).round() as i16);
+    }
+    stations
+}
+
+pub fn fixture_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+pub fn fixture(name: &str) -> Vec<u8> {
+    std::fs::read(fixture_path(name)).unwrap()
+}
+
+/// 10,000 distinct stations, each seen a few times, from a fixed-seed LCG.
+pub fn distinct_10k() -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut next = move || {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (state >> 33) as u32
+    };
+    let mut input = Vec::new();
+    for i in 0..40_000u32 {
+        let station = if i < 10_000 { i } else { next() % 10_000 };
+        let tenths = (next() % 1999) as i32 - 999;
+        let sign = if tenths < 0 { "-" } else { "" };
+        let line = format!("Station {:05};{}{}.{}\n", station, sign, tenths.abs() / 10, tenths.abs() % 10);
+        input.extend_from_slice(line.as_bytes());
+    }
+    input
+}
+
+/// A million rows of the official stations from the generator: several segments, and a
+/// few thousand values per station.
+pub fn generated() -> Vec<u8> {
+    let mut input = Vec::new();
+    let config = Config { rows: 1_000_000, seed: 24, stations: StationSet::Official };
+    generate::generate(&mut input, &config).unwrap();
+    input
+}
+
+pub struct Fixture {
+    pub name: &'static str,
+    pub input: Vec<u8>,
+}
+
+pub fn fixtures() -> Vec<Fixture> {
+    vec![
+        Fixture { name: "short.txt", input: fixture("short.txt") },
+        Fixture { name: "single_line.txt", input: fixture("single_line.txt") },
+        Fixture { name: "no_trailing_newline.txt", input: fixture("no_trailing_newline.txt") },
+        Fixture { name: "utf8.txt", input: fixture("utf8.txt") },
+        Fixture { name: "long_names.txt", input: fixture("long_names.txt") },
+        Fixture { name: "extremes.txt", input: fixture("extremes.txt") },
+        Fixture { name: "distinct_10k", input: distinct_10k() },
+    ]
+}
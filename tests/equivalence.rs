@@ -0,0 +1,65 @@
+mod common;
+
+use rs_1brc::aggregator::{Aggregator, APPROACHES};
+use rs_1brc::input::Backend;
+use rs_1brc::{approach_11, Error};
+
+use common::{distinct_10k, fixture, fixture_path, fixtures, oracle};
+
+#[test]
+fn oracle_matches_reference_output() {
+    let expected = String::from_utf8(fixture("short.out")).unwrap();
+    assert_eq!(rs_1brc::render(&oracle(&fixture("short.txt"))), expected.trim_end());
+}
+
+#[test]
+fn every_approach_matches_the_oracle() {
+    for fixture in fixtures() {
+        let expected = oracle(&fixture.input);
+        for (name, aggregator) in APPROACHES.iter() {
+            for threads in [1, 4] {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .unwrap();
+                let actual = pool.install(|| aggregator.aggregate(&fixture.input));
+                assert_eq!(actual.as_ref(), Ok(&expected), "{} on {} with {} threads", name, fixture.name, threads);
+            }
+        }
+    }
+}
+
+#[test]
+fn every_approach_renders_the_reference_output() {
+    let input = fixture("short.txt");
+    let expected = String::from_utf8(fixture("short.out")).unwrap();
+    for (name, aggregator) in APPROACHES.iter() {
+        assert_eq!(rs_1brc::render(&aggregator.aggregate(&input).unwrap()), expected.trim_end(), "{}", name);
+    }
+}
+
+#[test]
+fn approaches_pread_files_or_refuse_to() {
+    let path = std::env::temp_dir().join(format!("rs-1brc-equivalence-{}", std::process::id()));
+    std::fs::write(&path, distinct_10k()).unwrap();
+    let paths = [fixture_path("short.txt"), fixture_path("no_trailing_newline.txt"), path.clone()];
+    // Small segments, so that one file is read in many pieces.
+    let small = approach_11::Approach::new(3, 4096);
+    let approaches = APPROACHES.iter().copied().chain([("approach_11 small", &small as &dyn Aggregator)]);
+    for (name, aggregator) in approaches {
+        for path in &paths {
+            let expected = oracle(&std::fs::read(path).unwrap());
+            match aggregator.aggregate_file_with(path, Backend::Read) {
+                Ok(actual) => {
+                    assert!(aggregator.preads(), "{}", name);
+                    assert_eq!(actual, expected, "{} on {}", name, path.display());
+                }
+                Err(Error::Io(err)) if !aggregator.preads() => {
+                    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported, "{}", name)
+                }
+                Err(err) => panic!("{} on {}: {}", name, path.display(), err),
+            }
+        }
+    }
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use rs_1brc::aggregator::APPROACHES;
+use rs_1brc::error::{ParseError, ParseErrorKind};
+use rs_1brc::OnError;
+
+use common::oracle;
+
+struct Case {
+    name: &'static str,
+    bad_line: Vec<u8>,
+    kind: ParseErrorKind,
+}
+
+fn case(name: &'static str, bad_line: &[u8], kind: ParseErrorKind) -> Case {
+    Case {
+        name,
+        bad_line: bad_line.to_vec(),
+        kind,
+    }
+}
+
+fn cases() -> Vec<Case> {
+    let mut long_name = vec![b'a'; 101];
+    long_name.extend_from_slice(b";1.0");
+    vec![
+        case("missing separator", b"Broken", ParseErrorKind::MissingSeparator),
+        case("empty line", b"", ParseErrorKind::MissingSeparator),
+        case("bad number", b"Cairo;12.34", ParseErrorKind::BadNumber),
+        case("empty number", b"Cairo;", ParseErrorKind::BadNumber),
+        case("invalid utf-8", b"Ca\xffro;1.0", ParseErrorKind::InvalidUtf8),
+        case("name too long", &long_name, ParseErrorKind::NameTooLong),
+    ]
+}
+
+fn valid_lines(range: std::ops::Range<usize>) -> Vec<u8> {
+    range
+        .flat_map(|i| format!("Station{};{}.{}\n", i % 13, i % 50, i % 10).into_bytes())
+        .collect()
+}
+
+/// Puts `bad` on lines 301 and 702 of otherwise valid input. Returns the input, the
+/// offset of the first bad line and the input without the bad lines.
+fn input_with(bad: &[u8]) -> (Vec<u8>, usize, Vec<u8>) {
+    let mut input = valid_lines(0..300);
+    let offset = input.len();
+    input.extend_from_slice(bad);
+    input.push(b'\n');
+    input.extend_from_slice(&valid_lines(300..700));
+    input.extend_from_slice(bad);
+    input.push(b'\n');
+    input.extend_from_slice(&valid_lines(700..1000));
+    let mut valid = valid_lines(0..300);
+    valid.extend_from_slice(&valid_lines(300..1000));
+    (input, offset, valid)
+}
+
+#[test]
+fn fail_reports_the_first_bad_line() {
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+    for case in cases() {
+        let (input, offset, _) = input_with(&case.bad_line);
+        let expected = ParseError { offset, line: 301, kind: case.kind };
+        for (name, aggregator) in APPROACHES.iter() {
+            let actual = pool.install(|| aggregator.aggregate(&input));
+            assert_eq!(actual, Err(expected), "{} on {}", name, case.name);
+        }
+    }
+}
+
+#[test]
+fn skip_drops_only_bad_lines() {
+    for case in cases() {
+        let (input, _, valid) = input_with(&case.bad_line);
+        let expected = oracle(&valid);
+        for (name, aggregator) in APPROACHES.iter() {
+            let actual = aggregator.aggregate_with(&input, OnError::Skip).unwrap();
+            assert_eq!(actual.stations, expected, "{} on {}", name, case.name);
+            assert_eq!(actual.rejected, 2, "{} on {}", name, case.name);
+            assert!(actual.errors.is_empty(), "{} on {}", name, case.name);
+        }
+    }
+}
+
+#[test]
+fn report_lists_bad_lines_in_order() {
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+    for case in cases() {
+        let (input, offset, valid) = input_with(&case.bad_line);
+        let second = offset + case.bad_line.len() + 1 + valid_lines(300..700).len();
+        let expected = vec![
+            ParseError { offset, line: 301, kind: case.kind },
+            ParseError { offset: second, line: 702, kind: case.kind },
+        ];
+        for (name, aggregator) in APPROACHES.iter() {
+            let actual = pool.install(|| aggregator.aggregate_with(&input, OnError::Report)).unwrap();
+            assert_eq!(actual.errors, expected, "{} on {}", name, case.name);
+            assert_eq!(actual.stations, oracle(&valid), "{} on {}", name, case.name);
+        }
+    }
+}
+
+#[test]
+fn bad_final_line_without_newline() {
+    for (name, aggregator) in APPROACHES.iter() {
+        let actual = aggregator.aggregate(b"Abha;1.0\nBroken");
+        let expected = ParseError { offset: 9, line: 2, kind: ParseErrorKind::MissingSeparator };
+        assert_eq!(actual, Err(expected), "{}", name);
+    }
+}
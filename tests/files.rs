@@ -0,0 +1,136 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rs_1brc::error::ParseErrorKind;
+use rs_1brc::files::{self, Options};
+use rs_1brc::input::Backend;
+use rs_1brc::{Error, OnError};
+
+use common::{distinct_10k, oracle};
+
+/// A scratch directory, removed again when the test ends.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("rs-1brc-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Scratch(dir)
+    }
+
+    fn write(&self, name: &str, bytes: &[u8]) -> PathBuf {
+        let path = self.0.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Splits `input` at line boundaries into `parts` pieces, the last without its `\n`.
+fn split(input: &[u8], parts: usize) -> Vec<&[u8]> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    for i in 1..=parts {
+        let end = if i == parts {
+            input.len() - 1
+        } else {
+            let at = input.len() * i / parts;
+            at + input[at..].iter().position(|&b| b == b'\n').unwrap() + 1
+        };
+        pieces.push(&input[start..end]);
+        start = end;
+    }
+    pieces
+}
+
+fn lines(bytes: &[u8]) -> u64 {
+    bytes.split(|&b| b == b'\n').filter(|line| !line.is_empty()).count() as u64
+}
+
+#[test]
+fn directories_and_globs_merge_into_one_result() {
+    let scratch = Scratch::new("files");
+    let input = distinct_10k();
+    let pieces = split(&input, 5);
+    let written = [
+        scratch.write("2024/01/00.txt", pieces[0]),
+        scratch.write("2024/01/01.txt", pieces[1]),
+        scratch.write("2024/02/00.txt", pieces[2]),
+        scratch.write("2024/02/01.txt", pieces[3]),
+        scratch.write("2024/empty.txt", b""),
+        scratch.write("2025.txt", pieces[4]),
+    ];
+    scratch.write("2024/.hidden", b"not;a line at all\n");
+    let rows_of = |path: &Path| {
+        let i = written.iter().position(|written| written == path).unwrap();
+        lines([pieces[0], pieces[1], pieces[2], pieces[3], b"", pieces[4]][i])
+    };
+
+    let expected = oracle(&input);
+    let dir = &scratch.0;
+    let patterns: [Vec<PathBuf>; 3] = [
+        vec![dir.clone()],
+        vec![dir.join("2024"), dir.join("2025.txt")],
+        vec![dir.join("2024/*/0?.txt"), dir.join("*.txt"), dir.join("2024/empty.txt")],
+    ];
+    for patterns in patterns {
+        let paths = files::expand(&patterns).unwrap();
+        assert_eq!(paths.len(), 6, "{:?}", paths);
+        for threads in [1, 4] {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            for backend in Backend::ALL {
+                let options = Options { backend, rows: true, ..Options::default() };
+                let result = pool.install(|| files::aggregate(&paths, options)).unwrap();
+                assert_eq!(result.stations, expected, "{:?} on {} threads with {}", patterns, threads, backend);
+                for file in &result.files {
+                    assert_eq!(file.rows, Some(rows_of(&file.path)), "{}", file.path.display());
+                }
+            }
+        }
+        assert_eq!(rs_1brc::aggregate_files(&patterns).unwrap(), expected);
+    }
+}
+
+#[test]
+fn errors_name_their_file() {
+    let scratch = Scratch::new("file-errors");
+    let good = scratch.write("a.txt", b"Abha;1.0\nAbha;2.0\n");
+    let bad = scratch.write("b.txt", b"Abha;3.0\nBroken\nCairo;12.34\n");
+    let worse = scratch.write("c.txt", b"Broken\n");
+    let paths = [good, bad.clone(), worse];
+
+    for backend in Backend::ALL {
+        match files::aggregate(&paths, Options { backend, ..Options::default() }) {
+            Err(err) => {
+                assert_eq!(err.path, bad);
+                match err.error {
+                    Error::Parse(err) => assert_eq!((err.line, err.offset), (2, 9)),
+                    Error::Io(err) => panic!("{}", err),
+                }
+            }
+            Ok(_) => panic!("malformed lines were accepted"),
+        }
+
+        let options = Options { backend, on_error: OnError::Report, rows: true, ..Options::default() };
+        let result = files::aggregate(&paths, options).unwrap();
+        assert_eq!(result.stations["Abha"].count, 3);
+        let summary: Vec<_> = result.files.iter().map(|file| (file.rows, file.rejected)).collect();
+        assert_eq!(summary, [(Some(2), 0), (Some(1), 2), (Some(0), 1)]);
+        let kinds: Vec<_> = result.files[1].errors.iter().map(|err| (err.line, err.kind)).collect();
+        assert_eq!(kinds, [(2, ParseErrorKind::MissingSeparator), (3, ParseErrorKind::BadNumber)]);
+    }
+
+    let missing = scratch.0.join("missing-*.txt");
+    let err = files::expand(&[&missing]).unwrap_err();
+    assert_eq!(err.path, missing);
+    assert!(files::aggregate(&[Path::new("/nonexistent").to_path_buf()], Options::default()).is_err());
+}
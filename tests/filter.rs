@@ -0,0 +1,76 @@
+mod common;
+
+use regex::Regex;
+use rs_1brc::approach_9::Filtered;
+use rs_1brc::files::{self, Options};
+use rs_1brc::reduce;
+use rs_1brc::{Aggregator, Backend, OnError, StationFilter, StationStats, Stations};
+
+use common::{fixture_path, fixtures, oracle};
+
+fn filters() -> Vec<StationFilter> {
+    vec![
+        StationFilter::default(),
+        StationFilter::names(["Station 00042", "Abha", "Zürich", "nowhere"]),
+        StationFilter { prefixes: vec!["Station 01".to_string(), "B".to_string()], ..StationFilter::default() },
+        StationFilter { patterns: vec![Regex::new("7$|^[^S]").unwrap()], ..StationFilter::default() },
+    ]
+}
+
+#[test]
+fn filtered_scans_match_the_filtered_oracle() {
+    for fixture in fixtures() {
+        for filter in filters() {
+            let mut expected = oracle(&fixture.input);
+            expected.retain(|name, _| filter.matches(name));
+            for threads in [1, 4] {
+                let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+                let result = pool
+                    .install(|| reduce::aggregate_filtered::<StationStats>(&fixture.input, &filter, OnError::Fail))
+                    .unwrap();
+                let stations: Stations = result.stations;
+                assert_eq!(stations, expected, "{} with {:?} on {} threads", fixture.name, filter, threads);
+            }
+        }
+    }
+}
+
+#[test]
+fn excluded_rows_are_not_parsed() {
+    let input = b"Abha;1.0\nCairo;12.34\nAbha;-2.0\nBroken\n";
+    let filter = StationFilter::names(["Abha"]);
+    let result = reduce::aggregate_filtered::<StationStats>(input, &filter, OnError::Skip).unwrap();
+    assert_eq!(result.stations.keys().collect::<Vec<_>>(), ["Abha"]);
+    assert_eq!(result.stations["Abha"].count, 2);
+    // The bad value of Cairo goes unnoticed, the line without a `;` does not.
+    assert_eq!(result.rejected, 1);
+}
+
+#[test]
+fn filtered_approach_matches_the_filtered_oracle() {
+    for fixture in fixtures() {
+        for filter in filters() {
+            let mut expected = oracle(&fixture.input);
+            expected.retain(|name, _| filter.matches(name));
+            let stations = Filtered::new(&filter).aggregate(&fixture.input).unwrap();
+            assert_eq!(stations, expected, "{} with {:?}", fixture.name, filter);
+        }
+    }
+}
+
+#[test]
+fn rows_count_only_the_selected_stations() {
+    let path = fixture_path("short.txt");
+    for filter in filters() {
+        let mut expected = oracle(&std::fs::read(&path).unwrap());
+        expected.retain(|name, _| filter.matches(name));
+        let rows = expected.values().map(|stats| stats.count).sum::<u64>();
+        for backend in Backend::ALL {
+            let options = Options { backend, rows: true, filter: filter.clone(), ..Options::default() };
+            let result = files::aggregate(std::slice::from_ref(&path), options).unwrap();
+            assert_eq!(result.stations, expected, "{:?} with {:?}", backend, filter);
+            assert_eq!(result.files[0].rows, Some(rows), "{:?} with {:?}", backend, filter);
+            assert_eq!(rs_1brc::aggregate_file_filtered(&path, &filter).unwrap(), expected);
+        }
+    }
+}
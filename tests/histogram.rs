@@ -0,0 +1,31 @@
+mod common;
+
+use rs_1brc::histogram::{self, DEFAULT_PERCENTILES};
+use rs_1brc::OnError;
+
+use common::{fixtures, oracle, values_by_station};
+
+#[test]
+fn percentiles_match_sorted_values() {
+    for fixture in fixtures() {
+        let result = histogram::aggregate_with(&fixture.input, OnError::Fail).unwrap();
+        let stats = result.stations.iter().map(|(name, h)| (name.clone(), h.stats())).collect();
+        assert_eq!(oracle(&fixture.input), stats, "{}", fixture.name);
+
+        for (name, mut values) in values_by_station(&fixture.input) {
+            values.sort_unstable();
+            let histogram = &result.stations[&name];
+            for p in DEFAULT_PERCENTILES.into_iter().chain([0.0, 25.0, 99.9, 100.0]) {
+                let rank = ((p * values.len() as f64 / 100.0).ceil() as usize).max(1);
+                assert_eq!(
+                    histogram.percentile(p),
+                    Some(values[rank - 1]),
+                    "p{} of {} in {}",
+                    p,
+                    name,
+                    fixture.name
+                );
+            }
+        }
+    }
+}
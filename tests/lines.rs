@@ -0,0 +1,99 @@
+use rs_1brc::aggregator::APPROACHES;
+use rs_1brc::error::{Aggregate, ParseError};
+use rs_1brc::parse::{parse_record, station_name};
+use rs_1brc::scan::split_line;
+use rs_1brc::{OnError, Stations};
+
+struct Lcg(u64);
+
+impl Lcg {
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (self.0 >> 33) % n
+    }
+}
+
+fn name(rng: &mut Lcg, len: usize) -> Vec<u8> {
+    const CHARS: [&str; 6] = ["a", "Z", " ", "-", "é", "東"];
+    let mut name = Vec::new();
+    while name.len() < len {
+        let c = CHARS[rng.below(CHARS.len() as u64) as usize];
+        let c = if name.len() + c.len() > len { "x" } else { c };
+        name.extend_from_slice(c.as_bytes());
+    }
+    name
+}
+
+fn value(rng: &mut Lcg) -> Vec<u8> {
+    let tenths = rng.below(1999) as i32 - 999;
+    let sign = if tenths < 0 { "-" } else { "" };
+    format!("{}{}.{}", sign, tenths.abs() / 10, tenths.abs() % 10).into_bytes()
+}
+
+/// Lines of every length up to the spec maximum and a little beyond, some malformed,
+/// with or without a final `\n`.
+fn random_input(rng: &mut Lcg) -> Vec<u8> {
+    let mut input = Vec::new();
+    for _ in 0..rng.below(40) {
+        let len = 1 + rng.below(100) as usize;
+        match rng.below(20) {
+            0 => input.extend_from_slice(&name(rng, len)),
+            1 => {
+                let len = 101 + rng.below(30) as usize;
+                input.extend_from_slice(&name(rng, len));
+                input.push(b';');
+                input.extend_from_slice(&value(rng));
+            }
+            2 => input.extend_from_slice(b"Abha;1.23"),
+            _ => {
+                // Names only depend on their length, so stations repeat.
+                input.extend_from_slice(&name(&mut Lcg(len as u64 % 7), len));
+                input.push(b';');
+                input.extend_from_slice(&value(rng));
+            }
+        }
+        input.push(b'\n');
+    }
+    if rng.below(2) == 0 {
+        input.pop();
+    }
+    input
+}
+
+/// Aggregates `input` line by line with the scalar splitter.
+fn reference(input: &[u8]) -> Aggregate {
+    let mut stations = Stations::new();
+    let mut errors = Vec::new();
+    let mut offset = Some(0);
+    let mut line = 1;
+    while let Some(start) = offset.filter(|&o| o < input.len()) {
+        let (key, value, next) = split_line(input, start);
+        offset = next;
+        match parse_record(key, value).and_then(|value| Ok((station_name(key)?, value))) {
+            Ok((key, value)) => stations.entry(key.to_string()).or_default().update(value),
+            Err(kind) => errors.push(ParseError { offset: start, line, kind }),
+        }
+        line += 1;
+    }
+    Aggregate { stations, rejected: errors.len() as u64, errors, workers: Vec::new() }
+}
+
+#[test]
+fn every_approach_splits_lines_like_the_scalar_splitter() {
+    let mut rng = Lcg(0x1b2c);
+    let pools: Vec<_> = [1, 3]
+        .into_iter()
+        .map(|threads| rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap())
+        .collect();
+    for case in 0..300 {
+        let input = random_input(&mut rng);
+        let expected = reference(&input);
+        for (name, aggregator) in APPROACHES.iter() {
+            for pool in &pools {
+                let actual = pool.install(|| aggregator.aggregate_with(&input, OnError::Report));
+                let input = String::from_utf8_lossy(&input);
+                assert_eq!(actual.as_ref(), Ok(&expected), "{} on case {}: {:?}", name, case, input);
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use rs_1brc::reduce::{self, CountAbove, First, Last, Reducer};
+use rs_1brc::OnError;
+
+use common::{generated, values_by_station};
+
+/// A statistic the crate does not know about, defined outside of it.
+#[derive(Clone)]
+struct Spread {
+    min: i16,
+    max: i16,
+}
+
+impl Reducer for Spread {
+    type Value = i16;
+    type Output = i16;
+
+    fn init() -> Self {
+        Spread { min: i16::MAX, max: i16::MIN }
+    }
+
+    fn update(&mut self, value: i16) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    fn finish(&self) -> i16 {
+        self.max - self.min
+    }
+}
+
+#[test]
+fn reducers_see_every_value_in_order() {
+    // Several segments, so that workers see rows out of order.
+    let input = generated();
+    let expected = values_by_station(&input);
+
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+    let first = pool.install(|| reduce::aggregate::<First>(&input, OnError::Fail)).unwrap().stations;
+    let last = pool.install(|| reduce::aggregate::<Last>(&input, OnError::Fail)).unwrap().stations;
+    let warm = reduce::aggregate::<CountAbove<300>>(&input, OnError::Fail).unwrap().stations;
+    let spread = reduce::aggregate::<Spread>(&input, OnError::Fail).unwrap().stations;
+    assert_eq!(first.len(), expected.len());
+    for (name, values) in &expected {
+        assert_eq!(first[name], values.first().copied(), "{}", name);
+        assert_eq!(last[name], values.last().copied(), "{}", name);
+        assert_eq!(warm[name], values.iter().filter(|&&v| v > 300).count() as u64, "{}", name);
+        let (min, max) = (values.iter().min().unwrap(), values.iter().max().unwrap());
+        assert_eq!(spread[name], max - min, "{}", name);
+    }
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use rs_1brc::generate::Rng;
+use rs_1brc::{histogram, sketch, OnError};
+
+use common::{generated, values_by_station};
+
+#[test]
+fn sketches_stay_within_their_bounds_of_the_exact_mode() {
+    let input = generated();
+    let sketches = sketch::aggregate_with(&input, OnError::Fail).unwrap().stations;
+    let exact = histogram::aggregate_with(&input, OnError::Fail).unwrap().stations;
+    let values = values_by_station(&input);
+    assert_eq!(sketches.len(), exact.len());
+    for ((name, sketch), histogram) in sketches.iter().zip(exact.values()) {
+        let stats = histogram.stats();
+        let exact = (stats.count, stats.min as f64 / 10.0, stats.max as f64 / 10.0);
+        assert_eq!((sketch.stats.count, sketch.stats.min, sketch.stats.max), exact, "{}", name);
+        assert!((sketch.stats.mean() - stats.mean()).abs() < 1e-9, "{}", name);
+        assert!((sketch.stats.stddev() - stats.stddev()).abs() < 1e-9, "{}", name);
+
+        let mut values: Vec<f64> = values[name].iter().map(|&v| v as f64 / 10.0).collect();
+        values.sort_unstable_by(|a, b| a.total_cmp(b));
+        let n = values.len() as f64;
+        for p in [1.0, 10.0, 50.0, 90.0, 99.0] {
+            let value = sketch.quantiles.percentile(p).unwrap();
+            let below = values.partition_point(|&v| v < value) as f64 / n;
+            let upto = values.partition_point(|&v| v <= value) as f64 / n;
+            let error = (below - p / 100.0).max(p / 100.0 - upto).max(0.0);
+            let exact = histogram.percentile(p).unwrap() as f64 / 10.0;
+            assert!(error < 0.0165, "p{} of {}: {} for {}, {:.4} off in rank", p, name, value, exact, error);
+        }
+
+        values.dedup();
+        let distinct = values.len() as f64;
+        let estimate = sketch.distinct.estimate() as f64;
+        assert!((estimate - distinct).abs() <= distinct * 0.05, "{}: {} for {}", name, estimate, distinct);
+    }
+}
+
+#[test]
+fn sketches_read_decimals_of_any_precision() {
+    // Readings with up to three decimals and far outside the 1BRC range.
+    let mut rng = Rng::new(23);
+    let mut input = Vec::new();
+    let mut values: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    let mut push = |name: String, value: &str| {
+        input.extend(format!("{};{}\n", name, value).into_bytes());
+        values.entry(name).or_default().push(value.parse().unwrap());
+    };
+    for _ in 0..300_000 {
+        let value = (rng.below(2_000_001) as f64 - 1_000_000.0) / 1000.0;
+        push(format!("Station {}", rng.below(20)), &format!("{:.*}", rng.below(4) as usize, value));
+    }
+    push("Station 0".to_string(), "123.45");
+    push("Station 0".to_string(), "-250.125");
+    assert!(histogram::aggregate_with(&input, OnError::Fail).is_err());
+
+    let sketches = sketch::aggregate_with(&input, OnError::Fail).unwrap().stations;
+    assert_eq!(sketches.len(), values.len());
+    for ((name, sketch), values) in sketches.iter().zip(values.values_mut()) {
+        values.sort_unstable_by(|a, b| a.total_cmp(b));
+        let n = values.len() as f64;
+        let exact = (values.len() as u64, values[0], values[values.len() - 1]);
+        assert_eq!((sketch.stats.count, sketch.stats.min, sketch.stats.max), exact, "{}", name);
+        let mean = values.iter().sum::<f64>() / n;
+        assert!((sketch.stats.mean() - mean).abs() < 1e-6, "{}: {} for {}", name, sketch.stats.mean(), mean);
+
+        for p in [1.0, 10.0, 50.0, 90.0, 99.0] {
+            let value = sketch.quantiles.percentile(p).unwrap();
+            assert!(values.binary_search_by(|v| v.total_cmp(&value)).is_ok(), "p{} of {}: {} was never read", p, name, value);
+            let below = values.partition_point(|&v| v < value) as f64 / n;
+            let upto = values.partition_point(|&v| v <= value) as f64 / n;
+            let error = (below - p / 100.0).max(p / 100.0 - upto).max(0.0);
+            assert!(error < 0.0165, "p{} of {}: {}, {:.4} off in rank", p, name, value, error);
+        }
+
+        let mut distinct = values.clone();
+        distinct.dedup();
+        let distinct = distinct.len() as f64;
+        let estimate = sketch.distinct.estimate() as f64;
+        assert!((estimate - distinct).abs() <= distinct * 0.05, "{}: {} for {}", name, estimate, distinct);
+    }
+}
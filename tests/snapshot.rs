@@ -0,0 +1,29 @@
+mod common;
+
+use rs_1brc::{best, snapshot};
+
+use common::fixtures;
+
+#[test]
+fn merged_shards_match_the_whole_input() {
+    for fixture in fixtures() {
+        let expected = best().aggregate(&fixture.input).unwrap();
+        for shards in [1, 2, 7] {
+            let mut partials = Vec::new();
+            let mut start = 0;
+            for i in 1..=shards {
+                let at = fixture.input.len() * i / shards;
+                let end = fixture.input[at..]
+                    .iter()
+                    .position(|&b| b == b'\n')
+                    .map_or(fixture.input.len(), |pos| at + pos + 1);
+                let stations = best().aggregate(&fixture.input[start..end]).unwrap();
+                let mut bytes = Vec::new();
+                snapshot::save(&stations, &mut bytes).unwrap();
+                partials.push(snapshot::load(&bytes[..]).unwrap());
+                start = end;
+            }
+            assert_eq!(snapshot::merge(partials), expected, "{} in {} shards", fixture.name, shards);
+        }
+    }
+}
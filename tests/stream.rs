@@ -0,0 +1,103 @@
+mod common;
+
+use std::io::Read;
+
+use rs_1brc::aggregator::by_name;
+use rs_1brc::stream::aggregate_reader;
+use rs_1brc::{Error, OnError};
+
+use common::fixtures;
+
+/// Hands out at most `step` bytes per read, like a pipe would.
+struct Trickle<'a> {
+    input: &'a [u8],
+    step: usize,
+}
+
+impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.step.min(buf.len()).min(self.input.len());
+        buf[..n].copy_from_slice(&self.input[..n]);
+        self.input = &self.input[n..];
+        Ok(n)
+    }
+}
+
+/// Yields `head`, then a line that never ends, one byte at a time like a slow pipe.
+struct Endless<'a> {
+    head: &'a [u8],
+}
+
+impl Read for Endless<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let n = match self.head.len().min(buf.len()) {
+            0 => {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+                buf[0] = b'0';
+                1
+            }
+            n => {
+                buf[..n].copy_from_slice(&self.head[..n]);
+                self.head = &self.head[n..];
+                n
+            }
+        };
+        Ok(n)
+    }
+}
+
+fn with_bad_lines(input: &[u8]) -> Vec<u8> {
+    let mut input = input.to_vec();
+    for (i, bad) in [&b"Broken\n"[..], b"Cairo;12.34\n", b"\n"].into_iter().enumerate() {
+        let at = input.len() * (i + 1) / 4;
+        let at = input[..at].iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        input.splice(at..at, bad.iter().copied());
+    }
+    input
+}
+
+#[test]
+fn streaming_matches_the_whole_input() {
+    for fixture in fixtures() {
+        for input in [fixture.input.clone(), with_bad_lines(&fixture.input)] {
+            for name in ["approach_0", "approach_9"] {
+                let aggregator = by_name(name).unwrap();
+                for policy in [OnError::Fail, OnError::Report] {
+                    let expected = aggregator.aggregate_with(&input, policy);
+                    // Buffers from a single line to the whole input, in about as many reads;
+                    // tiny buffers are too slow for the larger fixtures.
+                    let tiny = if input.len() < 1 << 12 { &[1, 7, 64][..] } else { &[] };
+                    for &buffer_size in tiny.iter().chain(&[input.len() / 20, input.len()]) {
+                        let step = (buffer_size / 3).max(1);
+                        let reader = Trickle { input: &input, step };
+                        let actual = aggregate_reader(aggregator, reader, policy, buffer_size);
+                        let actual = match actual {
+                            Ok(actual) => Ok(actual),
+                            Err(Error::Parse(err)) => Err(err),
+                            Err(Error::Io(err)) => panic!("{}", err),
+                        };
+                        assert_eq!(
+                            actual, expected,
+                            "{} on {} with {:?}, {} byte buffers",
+                            name, fixture.name, policy, buffer_size
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn errors_do_not_wait_for_the_rest_of_the_stream() {
+    let aggregator = by_name("approach_9").unwrap();
+    let reader = Endless { head: b"Broken\nAbha;1." };
+    match aggregate_reader(aggregator, reader, OnError::Fail, 8) {
+        Err(Error::Parse(err)) => assert_eq!((err.line, err.offset), (1, 0)),
+        Err(Error::Io(err)) => panic!("{}", err),
+        Ok(_) => panic!("a line without `;` was accepted"),
+    }
+}